@@ -0,0 +1,30 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Embeds the commit the binary was built from, and that commit's date, so
+/// `bullhorn --version` can be traced back to the source. Both are left out
+/// when building outside of a git checkout, like from a source tarball.
+fn main() {
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    if let Some(commit) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=BULLHORN_GIT_COMMIT={}", commit);
+    }
+    // the commit date rather than the time of the build keeps builds reproducible
+    if let Some(date) = git(&["log", "-1", "--format=%cs"]) {
+        println!("cargo:rustc-env=BULLHORN_COMMIT_DATE={}", date);
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
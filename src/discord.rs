@@ -0,0 +1,60 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::sink::{Notification, NotificationSink};
+
+#[derive(Debug, Clone)]
+pub struct DiscordWebhookClient {
+    api: reqwest::Client,
+    webhook_url: String,
+}
+
+impl DiscordWebhookClient {
+    pub fn new(api: reqwest::Client, webhook_url: impl ToString) -> Self {
+        Self {
+            api,
+            webhook_url: webhook_url.to_string(),
+        }
+    }
+
+    async fn post(&self, notification: &Notification) -> Result<()> {
+        // Discord only follows http links, so nostr: uris are left off the embed
+        let url = notification
+            .click
+            .iter()
+            .chain(notification.actions.iter().map(|action| &action.url))
+            .find(|url| url.starts_with("https://") || url.starts_with("http://"));
+        let mut embed = json!({
+            "title": notification.title,
+            "description": notification.message,
+        });
+        if let Some(url) = url {
+            embed["url"] = json!(url);
+        }
+        if let Some(icon) = &notification.icon {
+            embed["thumbnail"] = json!({ "url": icon });
+        }
+
+        let response = self
+            .api
+            .post(&self.webhook_url)
+            .json(&json!({ "embeds": [embed] }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Discord responded with {}: {}", status, body.trim());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NotificationSink for DiscordWebhookClient {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        self.post(notification).await
+    }
+}
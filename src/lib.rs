@@ -0,0 +1,431 @@
+//! Simple nostr notification service.
+//!
+//! [`Bullhorn`] watches relays for events sent to the configured pubkeys and
+//! delivers notifications about them until told to shut down, so it can run
+//! inside another async app as well as on its own.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use nostr_sdk::Keys;
+use reqwest::StatusCode;
+use tokio::fs::create_dir_all;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, warn};
+
+use crate::discord::DiscordWebhookClient;
+use crate::messages::{Messages, DEFAULT_LOCALE};
+use crate::metrics::serve_metrics;
+use crate::nostr::{
+    add_dm_relays, add_inbox_relays, count_unverified_zaps, get_client, proxy_socket_addr,
+    prune_database, watch_pubkey_receives, Checkpoints, Followers, LiveEventStatuses, Lookbacks,
+    MetadataCache, ReceivedEvent, ReconnectOptions, SeenCoordinates, SeenEvents, WatchTargets,
+    WatcherOptions, WatcherState, DEFAULT_AUTHOR_NOTE_LOOKBACK_HOURS,
+    DEFAULT_CLOCK_SKEW_TOLERANCE_SECS, DEFAULT_COMMENT_DELETION_GRACE_SECS,
+    DEFAULT_DB_RETENTION_SECS, DEFAULT_EVENT_CHANNEL_CAPACITY, DEFAULT_LIVE_EVENT_LOOKBACK_HOURS,
+    DEFAULT_RECONNECT_MAX_BACKOFF_SECS, DEFAULT_RECONNECT_MAX_RETRIES,
+};
+use crate::ntfy::{
+    send_notifications, watch_connectivity, DigestOptions, LiveEventOptions, LiveEventState,
+    Notifier, NotifierOptions, NtfyApiClient, NtfyAuth, Priority, Reminders, ZapOptions,
+    DEFAULT_COMMENT_AGGREGATION_SECS, DEFAULT_DM_AGGREGATION_SECS,
+    DEFAULT_EVENT_REMINDER_OFFSETS_MINS, DEFAULT_MAX_PENDING_REMINDERS, DEFAULT_MAX_RETRIES,
+    DEFAULT_RELAYS_DOWN_GRACE_SECS, DEFAULT_TIMEOUT_SECS, DEFAULT_WEB_VIEWER,
+    DEFAULT_ZAP_AGGREGATION_SECS,
+};
+use crate::price::{PriceCache, DEFAULT_PRICE_API};
+use crate::rate_limit::{
+    RateLimitedSink, DEFAULT_NOTIFICATION_BURST, DEFAULT_NOTIFICATION_RATE_PER_SEC,
+};
+use crate::relay_info::RelayInfo;
+use crate::settings::data_dir;
+use crate::sink::{Notification, NotificationSink, SinkKind};
+use crate::telegram::TelegramClient;
+use crate::topic::get_subscription_topic;
+
+pub use crate::settings::Config;
+
+pub mod discord;
+pub mod messages;
+pub mod metrics;
+pub mod nostr;
+pub mod ntfy;
+pub mod price;
+pub mod rate_limit;
+pub mod relay_info;
+pub mod settings;
+pub mod sink;
+pub mod telegram;
+pub mod topic;
+
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long to wait for queued notifications to be delivered on shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const TEST_MESSAGE: &str = "Bullhorn is connected!";
+
+/// The watcher and notifier, built from a [`Config`].
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let config = bullhorn::settings::get_config().await?;
+/// bullhorn::Bullhorn::new(config)
+///     .run(async {
+///         let _ = tokio::signal::ctrl_c().await;
+///     })
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Bullhorn {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Bullhorn {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            dry_run: false,
+        }
+    }
+
+    /// Log notifications instead of sending them.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sends a test notification through the configured sink. ntfy's response
+    /// status is returned so it can be checked.
+    pub async fn send_test_notification(&self) -> Result<Option<StatusCode>> {
+        let http_client = self.http_client()?;
+        if self.config.sink == SinkKind::Ntfy {
+            let status = self
+                .ntfy_client(http_client)
+                .await?
+                .send_test(TEST_MESSAGE)
+                .await?;
+            return Ok(Some(status));
+        }
+
+        let notification = Notification {
+            kind: "test",
+            title: "Bullhorn".to_string(),
+            message: TEST_MESSAGE.to_string(),
+            priority: Priority::Default,
+            tags: "white_check_mark".to_string(),
+            markdown: false,
+            click: None,
+            actions: Vec::new(),
+            icon: None,
+            at: None,
+        };
+        self.sink(http_client).await?.deliver(&notification).await?;
+        Ok(None)
+    }
+
+    /// Watches for events and notifies about them until `shutdown` completes.
+    /// Queued notifications are given a chance to be delivered before
+    /// returning.
+    pub async fn run(self, shutdown_signal: impl Future<Output = ()>) -> Result<()> {
+        if self.dry_run {
+            warn!("Dry run mode is enabled. Notifications will be logged, not sent.");
+        }
+        let http_client = self.http_client()?;
+        let sink = self.sink(http_client.clone()).await?;
+        let dry_run = self.dry_run;
+        let cfg = self.config;
+        let (sink, deliver_queued) = RateLimitedSink::new(
+            sink,
+            cfg.notification_rate_per_sec
+                .unwrap_or(DEFAULT_NOTIFICATION_RATE_PER_SEC),
+            cfg.notification_burst.unwrap_or(DEFAULT_NOTIFICATION_BURST),
+        );
+
+        count_unverified_zaps(cfg.count_unverified_zaps);
+        let keys = cfg.nsec.as_deref().map(Keys::parse).transpose()?;
+        let auth_keys = keys.as_ref().filter(|_| cfg.relay_auth);
+        let proxy = cfg.proxy.as_deref().map(proxy_socket_addr).transpose()?;
+        match &cfg.proxy {
+            Some(url) => info!("Connecting to relays and ntfy through proxy {}", url),
+            None => info!("Connecting to relays and ntfy directly"),
+        }
+        let price = cfg.fiat_currency.as_deref().map(|currency| {
+            let url = cfg.price_api_url.as_deref().unwrap_or(DEFAULT_PRICE_API);
+            PriceCache::new(http_client, url, currency)
+        });
+
+        let nostr_client = get_client(
+            &cfg.ndb_path,
+            cfg.relays.clone(),
+            cfg.max_relays,
+            auth_keys,
+            proxy,
+        )
+        .await?;
+        if let Err(err) = add_inbox_relays(&nostr_client, &cfg.pubkeys).await {
+            warn!("Unable to add relays from your relay list: {}", err);
+        }
+        // DMs can only be read with an nsec, so their relays are otherwise skipped
+        let dm_relays = match &keys {
+            Some(_) => add_dm_relays(&nostr_client, &cfg.pubkeys)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!("Unable to add relays from your DM relay list: {}", err);
+                    Vec::new()
+                }),
+            None => Vec::new(),
+        };
+        let relay_info = RelayInfo::fetch(&nostr_client, proxy, auth_keys.is_some()).await;
+        let locale = cfg.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+        let mut messages = Messages::for_locale(locale)
+            .with_context(|| format!("No translations for locale {}", locale))?;
+        cfg.titles.apply(&mut messages);
+        cfg.templates.apply(&mut messages);
+        let notifier = Notifier::new(Arc::new(sink))
+            .with_priorities(cfg.priorities.clone())
+            .with_messages(messages)
+            .with_tags(cfg.tags.clone())
+            .with_zap_tiers(cfg.zap_tiers.clone())
+            .with_web_viewer(cfg.web_viewer.as_deref().unwrap_or(DEFAULT_WEB_VIEWER))
+            .with_markdown(cfg.markdown)
+            .with_dry_run(dry_run)
+            .with_debug_event_ids(cfg.debug_include_event_id)
+            .with_quiet_hours(cfg.quiet_hours.clone());
+
+        let data_dir = data_dir()?;
+        create_dir_all(&data_dir).await?;
+        let seen_events = SeenEvents::load(data_dir.join("seen_events")).await?;
+        let checkpoints = Checkpoints::load(data_dir.join("checkpoints")).await?;
+        let private_dm_since = checkpoints.private_dm_since();
+        let live_statuses = LiveEventStatuses::load(data_dir.join("live_events")).await?;
+        let followers = Followers::load(data_dir.join("followers")).await?;
+        let articles = SeenCoordinates::load(data_dir.join("articles")).await?;
+        let recordings = SeenCoordinates::load(data_dir.join("recordings")).await?;
+        let reminders = Reminders::load(
+            data_dir.join("reminders"),
+            cfg.max_pending_reminders
+                .unwrap_or(DEFAULT_MAX_PENDING_REMINDERS),
+        )
+        .await?;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel::<ReceivedEvent>(
+            cfg.event_channel_capacity
+                .unwrap_or(DEFAULT_EVENT_CHANNEL_CAPACITY),
+        );
+        let tracker = TaskTracker::new();
+        let shutdown = CancellationToken::new();
+        // finishes once the notifier is done with the sink and the queue is
+        // delivered, so shutdown waits on it too
+        tracker.spawn(deliver_queued);
+
+        tracker.spawn(watch_pubkey_receives(
+            nostr_client.clone(),
+            WatchTargets {
+                pubkeys: cfg.pubkeys,
+                event_npubs: cfg.event_pubkeys,
+                dm_relays,
+                relay_info,
+            },
+            WatcherState {
+                seen: seen_events,
+                checkpoints,
+                followers,
+                articles,
+            },
+            WatcherOptions {
+                gift_wraps: keys.is_some(),
+                notify_unfollows: cfg.notify_unfollows,
+                notify_article_edits: cfg.notify_article_edits,
+                comment_deletion_grace: Duration::from_secs(
+                    cfg.comment_deletion_grace_secs
+                        .unwrap_or(DEFAULT_COMMENT_DELETION_GRACE_SECS),
+                ),
+                enabled: cfg.enabled_notifications.clone(),
+                reconnect: ReconnectOptions {
+                    max_retries: cfg
+                        .reconnect_max_retries
+                        .unwrap_or(DEFAULT_RECONNECT_MAX_RETRIES),
+                    max_backoff: Duration::from_secs(
+                        cfg.reconnect_max_backoff_secs
+                            .unwrap_or(DEFAULT_RECONNECT_MAX_BACKOFF_SECS),
+                    ),
+                },
+                lookbacks: Lookbacks {
+                    author_notes: Duration::from_secs(
+                        cfg.author_note_lookback_hours
+                            .unwrap_or(DEFAULT_AUTHOR_NOTE_LOOKBACK_HOURS)
+                            * 60
+                            * 60,
+                    ),
+                    live_events: Duration::from_secs(
+                        cfg.live_event_lookback_hours
+                            .unwrap_or(DEFAULT_LIVE_EVENT_LOOKBACK_HOURS)
+                            * 60
+                            * 60,
+                    ),
+                    clock_skew_tolerance: Duration::from_secs(
+                        cfg.clock_skew_tolerance_secs
+                            .unwrap_or(DEFAULT_CLOCK_SKEW_TOLERANCE_SECS),
+                    ),
+                },
+            },
+            sender,
+            tracker.clone(),
+            shutdown.clone(),
+        ));
+        let names = MetadataCache::new(nostr_client.clone(), METADATA_TTL);
+        let zap_options = ZapOptions {
+            min_sats: cfg.min_zap_sats,
+            window: Duration::from_secs(
+                cfg.zap_aggregation_secs
+                    .unwrap_or(DEFAULT_ZAP_AGGREGATION_SECS),
+            ),
+            price,
+            daily_summary: cfg.daily_summary.clone(),
+        };
+        let digest_options = DigestOptions {
+            dm_window: Duration::from_secs(
+                cfg.dm_aggregation_secs
+                    .unwrap_or(DEFAULT_DM_AGGREGATION_SECS),
+            ),
+            comment_window: Duration::from_secs(
+                cfg.comment_aggregation_secs
+                    .unwrap_or(DEFAULT_COMMENT_AGGREGATION_SECS),
+            ),
+        };
+        let live_event_options = LiveEventOptions {
+            notify_planned: cfg.notify_planned_events,
+            notify_ended: cfg.notify_ended_events,
+            notify_recordings: cfg.notify_event_recordings,
+            reminder_offsets: cfg
+                .event_reminder_offsets_mins
+                .clone()
+                .unwrap_or(DEFAULT_EVENT_REMINDER_OFFSETS_MINS.to_vec())
+                .into_iter()
+                .map(|mins| Duration::from_secs(mins * 60))
+                .collect(),
+        };
+        tracker.spawn(watch_connectivity(
+            nostr_client.clone(),
+            notifier.clone(),
+            Duration::from_secs(
+                cfg.relays_down_grace_secs
+                    .unwrap_or(DEFAULT_RELAYS_DOWN_GRACE_SECS),
+            ),
+            shutdown.clone(),
+        ));
+        tracker.spawn(send_notifications(
+            notifier,
+            names,
+            keys,
+            NotifierOptions {
+                zaps: zap_options,
+                digests: digest_options,
+                live_events: live_event_options,
+                senders: cfg.senders.clone(),
+                enabled: cfg.enabled_notifications.clone(),
+            },
+            LiveEventState {
+                statuses: live_statuses,
+                recordings,
+                reminders,
+            },
+            private_dm_since,
+            receiver,
+        ));
+        if let Some(port) = cfg.metrics_port {
+            let client = nostr_client.clone();
+            let shutdown = shutdown.clone();
+            tracker.spawn(async move {
+                if let Err(err) = serve_metrics(port, client, shutdown).await {
+                    error!("Metrics server stopped: {}", err);
+                }
+            });
+        }
+        tracker.spawn(prune_database(
+            nostr_client.clone(),
+            Duration::from_secs(cfg.db_retention_secs.unwrap_or(DEFAULT_DB_RETENTION_SECS)),
+            shutdown.clone(),
+        ));
+        tracker.close();
+
+        shutdown_signal.await;
+        info!("Shutdown signal received. Shutting down.");
+
+        // the watcher stops first so queued notifications are still delivered
+        // before the relays are disconnected
+        shutdown.cancel();
+        if timeout(SHUTDOWN_TIMEOUT, tracker.wait()).await.is_err() {
+            warn!(
+                "Pending notifications not delivered after {}s. Shutting down anyway.",
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+        }
+        nostr_client.shutdown().await?;
+        debug!("Nostr client disconnected");
+        info!("Successfully shut down.");
+
+        Ok(())
+    }
+
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let cfg = &self.config;
+        let ntfy_timeout =
+            Duration::from_secs(cfg.ntfy_timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+        let mut http_client = reqwest::Client::builder()
+            .connect_timeout(ntfy_timeout)
+            .timeout(ntfy_timeout);
+        if let Some(url) = &cfg.proxy {
+            http_client = http_client.proxy(reqwest::Proxy::all(url)?);
+        }
+        Ok(http_client.build()?)
+    }
+
+    async fn ntfy_client(&self, http_client: reqwest::Client) -> Result<NtfyApiClient> {
+        let cfg = &self.config;
+        let topic = get_subscription_topic().await?;
+        let ntfy_auth = NtfyAuth::new(
+            cfg.ntfy_token.as_deref().map(str::to_string),
+            cfg.ntfy_user.clone(),
+            cfg.ntfy_password.as_deref().map(str::to_string),
+        );
+        Ok(
+            NtfyApiClient::new(http_client, cfg.ntfy_server.as_deref(), topic, ntfy_auth)
+                .with_max_retries(cfg.ntfy_max_retries.unwrap_or(DEFAULT_MAX_RETRIES)),
+        )
+    }
+
+    async fn sink(&self, http_client: reqwest::Client) -> Result<Arc<dyn NotificationSink>> {
+        let cfg = &self.config;
+        let sink: Arc<dyn NotificationSink> = match cfg.sink {
+            SinkKind::Ntfy => Arc::new(self.ntfy_client(http_client).await?),
+            SinkKind::Discord => {
+                let Some(webhook_url) = &cfg.discord_webhook_url else {
+                    bail!("the discord sink requires discord_webhook_url to be set");
+                };
+                info!("Sending notifications to Discord");
+                Arc::new(DiscordWebhookClient::new(http_client, webhook_url.expose()))
+            }
+            SinkKind::Telegram => {
+                let (Some(bot_token), Some(chat_id)) =
+                    (&cfg.telegram_bot_token, &cfg.telegram_chat_id)
+                else {
+                    bail!(
+                        "the telegram sink requires telegram_bot_token and telegram_chat_id to be set"
+                    );
+                };
+                info!("Sending notifications to Telegram");
+                let telegram_client = TelegramClient::new(http_client, bot_token.expose(), chat_id)
+                    .with_web_viewer(cfg.web_viewer.as_deref().unwrap_or(DEFAULT_WEB_VIEWER));
+                Arc::new(telegram_client)
+            }
+        };
+
+        Ok(sink)
+    }
+}
@@ -1,119 +1,173 @@
-use anyhow::{bail, Result};
-use config::{Case, Environment, File};
-use log::{debug, info};
-use nostr::watch_pubkey_receives;
-use nostr_sdk::{Event, PublicKey};
-use ntfy::{send_ntfy_messages, NtfyApiClient};
-use qrcode::QrCode;
-use serde::Deserialize;
-use tokio::{
-    fs::{create_dir_all, read_to_string, write},
-    signal,
-};
-use tokio_util::task::TaskTracker;
-use uuid::Uuid;
-
-use crate::nostr::get_client;
-
-mod nostr;
-mod ntfy;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use bullhorn::settings::{describe_config, get_config};
+use bullhorn::sink::SinkKind;
+use bullhorn::topic::{get_subscription_topic, rotate_subscription_topic};
+use bullhorn::Bullhorn;
+use qrcode::{render::svg, QrCode};
+use tokio::{fs::write, select, signal};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse()?;
+    if args.version {
+        println!("{}", version());
+        return Ok(());
+    }
+
     if let Err(e) = dotenvy::dotenv() {
         if !e.not_found() {
             bail!(e)
         }
     }
-    env_logger::init();
+    init_logging();
+
+    if args.new_topic {
+        let topic = rotate_subscription_topic().await?;
+        display_subscription_qr(&topic.as_hyphenated().to_string(), None).await?;
+        println!();
+        println!("Restart bullhorn for notifications to be sent to the new topic.");
+        return Ok(());
+    }
+
+    if args.print_config {
+        for line in describe_config()? {
+            println!("{}", line);
+        }
+        // loading it fully reports any problems with it
+        get_config().await?;
+        println!();
+        println!("The config is valid. Options not listed use their defaults.");
+        return Ok(());
+    }
+
     info!("Bullhorn process starting up.");
 
     let cfg = get_config().await?;
     debug!("config: {:?}", cfg);
 
-    let topic = get_subscription_topic().await?;
-    let nostr_client = get_client(&cfg.ndb_path).await?;
-    let http_client = reqwest::Client::builder().build()?;
-
-    display_subscription_qr(&topic.as_hyphenated().to_string());
-
-    let ntfy_client = NtfyApiClient::new(http_client, topic);
+    if cfg.sink == SinkKind::Ntfy {
+        let topic = get_subscription_topic().await?;
+        display_subscription_qr(
+            &topic.as_hyphenated().to_string(),
+            cfg.qr_output_path.as_deref(),
+        )
+        .await?;
+    }
 
-    let (sender, receiver) = tokio::sync::mpsc::channel::<Event>(300);
-    let tracker = TaskTracker::new();
+    let bullhorn = Bullhorn::new(cfg).with_dry_run(args.dry_run);
+    if args.test_notification {
+        match bullhorn.send_test_notification().await? {
+            Some(status) => println!("Test notification sent. ntfy responded with {}", status),
+            None => println!("Test notification sent."),
+        }
+        return Ok(());
+    }
 
-    tracker.spawn(watch_pubkey_receives(
-        nostr_client.clone(),
-        cfg.npub,
-        cfg.event_npubs,
-        sender,
-    ));
-    tracker.spawn(send_ntfy_messages(ntfy_client, receiver));
-    tracker.close();
+    bullhorn
+        .run(async {
+            if let Err(err) = shutdown_signal().await {
+                error!("Unable to listen for shutdown signal: {}", err);
+            }
+        })
+        .await
+}
 
-    if let Err(err) = signal::ctrl_c().await {
-        bail!("Unable to listen for shutdown signal: {}", err)
+/// Logs in a human-readable format unless `BULLHORN_LOG_FORMAT=json` asks for
+/// one JSON object per line, for log aggregators. This is read from the
+/// environment instead of the config so logging works before it's loaded.
+fn init_logging() {
+    let filter = EnvFilter::from_default_env();
+    match std::env::var("BULLHORN_LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_env_filter(filter)
+            .init(),
+        Ok("text") | Err(_) => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        Ok(format) => {
+            tracing_subscriber::fmt().with_env_filter(filter).init();
+            warn!("Unknown log format {}. Using text.", format);
+        }
     }
-    info!("Shutdown signal received. Shutting down.");
+}
 
-    nostr_client.shutdown().await?;
-    debug!("Nostr client disconnected");
-    tracker.wait().await;
-    info!("Successfully shut down.");
+/// Waits for Ctrl-C or for the service manager to stop us, like systemd and
+/// Docker do with SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    select! {
+        result = signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
 
+/// Waits for Ctrl-C or for the console window to be closed.
+#[cfg(windows)]
+async fn shutdown_signal() -> Result<()> {
+    let mut close = signal::windows::ctrl_close()?;
+    select! {
+        result = signal::ctrl_c() => result?,
+        _ = close.recv() => {}
+    }
     Ok(())
 }
 
-#[derive(Clone, Debug, Deserialize)]
-struct Config {
-    ndb_path: String,
-    npub: PublicKey,
-    event_npubs: Vec<PublicKey>,
+/// Command line flags. These are kept minimal, everything else lives in the
+/// config file.
+#[derive(Debug, Default)]
+struct Args {
+    /// Replace the subscription topic with a new one and exit.
+    new_topic: bool,
+    /// Log notifications instead of sending them.
+    dry_run: bool,
+    /// Send a test notification and exit.
+    test_notification: bool,
+    /// Print the version and exit.
+    version: bool,
+    /// Print the config, with secrets redacted, and exit.
+    print_config: bool,
 }
 
-async fn get_config() -> Result<Config> {
-    let data_dir = dirs::data_dir().unwrap().join("bullhorn");
-    let db_filepath = data_dir.join("nostr.db").into_os_string();
-    let db_filepath = db_filepath.to_str().unwrap();
-
-    let config_dir = dirs::config_dir().unwrap().join("bullhorn");
-    let config_file = config_dir.join("config.toml");
-
-    let cfg = config::Config::builder()
-        .add_source(
-            Environment::default()
-                .prefix("bullhorn")
-                .prefix_separator("_")
-                .convert_case(Case::UpperSnake)
-                .separator("__"),
-        )
-        .add_source(
-            File::from(config_file)
-                .required(false)
-                .format(config::FileFormat::Toml),
-        )
-        .set_default("ndb_path", db_filepath)?
-        .build()?;
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut args = Self::default();
+        for arg in std::env::args().skip(1) {
+            match arg.as_str() {
+                "--new-topic" => args.new_topic = true,
+                "--dry-run" => args.dry_run = true,
+                "--test-notification" => args.test_notification = true,
+                "--version" | "-V" => args.version = true,
+                "--print-config" => args.print_config = true,
+                _ => bail!("unknown argument '{}'", arg),
+            }
+        }
 
-    Ok(cfg.try_deserialize()?)
+        Ok(args)
+    }
 }
 
-async fn get_subscription_topic() -> Result<Uuid> {
-    let config_dir = dirs::config_dir().unwrap().join("bullhorn");
-    create_dir_all(config_dir.clone()).await?;
-
-    let filepath = config_dir.join("topic");
-    if let Ok(contents) = read_to_string(&filepath).await {
-        Ok(Uuid::parse_str(&contents)?)
-    } else {
-        let id = Uuid::new_v4();
-        write(filepath, id.as_hyphenated().to_string()).await?;
-        Ok(id)
+/// The crate version, followed by the commit it was built from and that
+/// commit's date when built from a git checkout.
+fn version() -> String {
+    let version = concat!("bullhorn ", env!("CARGO_PKG_VERSION"));
+    match (
+        option_env!("BULLHORN_GIT_COMMIT"),
+        option_env!("BULLHORN_COMMIT_DATE"),
+    ) {
+        (Some(commit), Some(date)) => format!("{} ({} {})", version, commit, date),
+        (Some(commit), None) => format!("{} ({})", version, commit),
+        _ => version.to_string(),
     }
 }
 
-fn display_subscription_qr(topic: &str) {
-    let code = QrCode::new(topic).unwrap();
+async fn display_subscription_qr(topic: &str, output_path: Option<&Path>) -> Result<()> {
+    let code = QrCode::new(topic).context("Unable to generate the subscription QR code")?;
     let string = code
         .render::<char>()
         .dark_color('#')
@@ -128,4 +182,14 @@ fn display_subscription_qr(topic: &str) {
     println!("{}", topic);
     println!();
     println!("Load this into the ntfy app to receive push notifications.");
+
+    // headless installs can't easily scan the terminal output, so save an
+    // image that can be opened elsewhere
+    if let Some(path) = output_path {
+        let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+        write(path, image).await?;
+        println!("The QR code was also saved to {}.", path.display());
+    }
+
+    Ok(())
 }
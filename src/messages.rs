@@ -0,0 +1,349 @@
+use serde::Deserialize;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The text of every notification, so it can be translated. Templates fill in
+/// `{placeholders}` with the values listed on each field.
+#[derive(Debug, Clone)]
+pub struct Messages {
+    pub dm_title: String,
+    pub zap_title: String,
+    pub comment_title: String,
+    pub mention_title: String,
+    pub reaction_title: String,
+    pub repost_title: String,
+    pub event_title: String,
+    pub article_title: String,
+    pub follow_title: String,
+    pub unfollow_title: String,
+    pub summary_title: String,
+    pub goal_title: String,
+    pub disconnected_title: String,
+    pub reconnected_title: String,
+
+    /// `{sender}`, `{content}`
+    pub dm_preview: String,
+    pub dm: String,
+    /// `{count}`
+    pub dm_digest: String,
+    /// `{sats}`
+    pub sats: String,
+    /// `{amount}`
+    pub zap_anonymous: String,
+    /// `{sender}`, `{amount}`
+    pub zap_single: String,
+    /// `{amount}`, `{count}`, `{top}`
+    pub zap_many: String,
+    /// `{amount}`, `{count}`, `{top}`
+    pub summary: String,
+    /// `{amount}`
+    pub summary_anonymous: String,
+    /// `{title}`, `{amount}`, `{target}`
+    pub goal: String,
+    /// `{duration}`
+    pub disconnected: String,
+    pub reconnected: String,
+    /// `{author}`
+    pub comment: String,
+    /// `{author}`, `{link}`
+    pub comment_link: String,
+    /// `{count}`, `{authors}`
+    pub comment_digest: String,
+    /// `{author}`
+    pub mention: String,
+    /// `{author}`, `{reaction}`
+    pub reaction: String,
+    /// `{author}`
+    pub repost: String,
+    /// `{author}`, `{title}`
+    pub article: String,
+    /// `{author}`
+    pub article_untitled: String,
+    /// `{name}`
+    pub follow: String,
+    /// `{name}`
+    pub unfollow: String,
+    /// `{title}`, `{duration}`
+    pub event_starts: String,
+    /// `{name}`, `{title}`, `{time}`
+    pub event_scheduled: String,
+    /// `{name}`, `{title}`
+    pub event_scheduled_untimed: String,
+    /// `{name}`, `{title}`
+    pub event_live: String,
+    /// `{title}`
+    pub event_ended: String,
+    /// `{title}`
+    pub event_recording: String,
+    /// `{id}`
+    pub event_untitled: String,
+    pub view_action: String,
+    pub reply_action: String,
+}
+
+impl Messages {
+    /// The messages for a locale like `es` or `es-MX`, if it's been translated.
+    pub fn for_locale(locale: &str) -> Option<Self> {
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        match language.to_lowercase().as_str() {
+            "en" => Some(Self::english()),
+            "es" => Some(Self::spanish()),
+            _ => None,
+        }
+    }
+
+    pub fn english() -> Self {
+        Self {
+            dm_title: "New DM Received".to_string(),
+            zap_title: "Zaps Received".to_string(),
+            comment_title: "Comment Received".to_string(),
+            mention_title: "Mentioned".to_string(),
+            reaction_title: "Reaction Received".to_string(),
+            repost_title: "Repost Received".to_string(),
+            event_title: "Event announcement".to_string(),
+            article_title: "New Article".to_string(),
+            follow_title: "New Follower".to_string(),
+            unfollow_title: "Unfollowed".to_string(),
+            summary_title: "Daily Zap Summary".to_string(),
+            goal_title: "Zap Goal Reached".to_string(),
+            disconnected_title: "Bullhorn Disconnected".to_string(),
+            reconnected_title: "Bullhorn Reconnected".to_string(),
+
+            dm_preview: "{sender}: {content}".to_string(),
+            dm: "You've received a new nostr DM.".to_string(),
+            dm_digest: "You've received {count} new nostr DMs.".to_string(),
+            sats: "{sats} sats".to_string(),
+            zap_anonymous: "You've received {amount} in zaps on your post!".to_string(),
+            zap_single: "{sender} zapped {amount}".to_string(),
+            zap_many: "You've received {amount} in zaps from {count} people! Top zappers: {top}"
+                .to_string(),
+            summary: "Today you received {amount} in zaps from {count} people. Top zappers: {top}"
+                .to_string(),
+            summary_anonymous: "Today you received {amount} in zaps.".to_string(),
+            goal: "Your goal {title} reached {amount} of {target}!".to_string(),
+            disconnected: "Bullhorn has been disconnected from every relay for {duration}. \
+                           You won't be notified until it reconnects."
+                .to_string(),
+            reconnected: "Bullhorn is connected to relays again.".to_string(),
+            comment: "{author} commented on your post".to_string(),
+            comment_link: "{author} [commented]({link}) on your post".to_string(),
+            comment_digest: "You've received {count} new comments from {authors}".to_string(),
+            mention: "{author} mentioned you in a post!".to_string(),
+            reaction: "{author} reacted {reaction} to your post!".to_string(),
+            repost: "{author} reposted your post!".to_string(),
+            article: "{author} published {title}".to_string(),
+            article_untitled: "{author} published a new article".to_string(),
+            follow: "{name} followed you!".to_string(),
+            unfollow: "{name} unfollowed you.".to_string(),
+            event_starts: "{title} starts in {duration}".to_string(),
+            event_scheduled: "{name} scheduled a live event for {time}: {title}".to_string(),
+            event_scheduled_untimed: "{name} scheduled a live event: {title}".to_string(),
+            event_live: "{name} is LIVE now: {title}".to_string(),
+            event_ended: "{title} has ended".to_string(),
+            event_recording: "A recording of {title} is available".to_string(),
+            event_untitled: "Event {id}".to_string(),
+            view_action: "View".to_string(),
+            reply_action: "Reply".to_string(),
+        }
+    }
+
+    pub fn spanish() -> Self {
+        Self {
+            dm_title: "Nuevo mensaje directo".to_string(),
+            zap_title: "Zaps recibidos".to_string(),
+            comment_title: "Comentario recibido".to_string(),
+            mention_title: "Te mencionaron".to_string(),
+            reaction_title: "Reacción recibida".to_string(),
+            repost_title: "Repost recibido".to_string(),
+            event_title: "Anuncio de evento".to_string(),
+            article_title: "Nuevo artículo".to_string(),
+            follow_title: "Nuevo seguidor".to_string(),
+            unfollow_title: "Dejaron de seguirte".to_string(),
+            summary_title: "Resumen diario de zaps".to_string(),
+            goal_title: "Meta de zaps alcanzada".to_string(),
+            disconnected_title: "Bullhorn desconectado".to_string(),
+            reconnected_title: "Bullhorn reconectado".to_string(),
+
+            dm_preview: "{sender}: {content}".to_string(),
+            dm: "Has recibido un nuevo mensaje directo de nostr.".to_string(),
+            dm_digest: "Has recibido {count} nuevos mensajes directos de nostr.".to_string(),
+            sats: "{sats} sats".to_string(),
+            zap_anonymous: "¡Has recibido {amount} en zaps en tu publicación!".to_string(),
+            zap_single: "{sender} te envió un zap de {amount}".to_string(),
+            zap_many: "¡Has recibido {amount} en zaps de {count} personas! Principales: {top}"
+                .to_string(),
+            summary: "Hoy recibiste {amount} en zaps de {count} personas. Principales: {top}"
+                .to_string(),
+            summary_anonymous: "Hoy recibiste {amount} en zaps.".to_string(),
+            goal: "¡Tu meta {title} alcanzó {amount} de {target}!".to_string(),
+            disconnected: "Bullhorn lleva {duration} desconectado de todos los relays. \
+                           No recibirás notificaciones hasta que se reconecte."
+                .to_string(),
+            reconnected: "Bullhorn está conectado a los relays de nuevo.".to_string(),
+            comment: "{author} comentó en tu publicación".to_string(),
+            comment_link: "{author} [comentó]({link}) en tu publicación".to_string(),
+            comment_digest: "Has recibido {count} nuevos comentarios de {authors}".to_string(),
+            mention: "¡{author} te mencionó en una publicación!".to_string(),
+            reaction: "¡{author} reaccionó {reaction} a tu publicación!".to_string(),
+            repost: "¡{author} compartió tu publicación!".to_string(),
+            article: "{author} publicó {title}".to_string(),
+            article_untitled: "{author} publicó un nuevo artículo".to_string(),
+            follow: "¡{name} comenzó a seguirte!".to_string(),
+            unfollow: "{name} dejó de seguirte.".to_string(),
+            event_starts: "{title} comienza en {duration}".to_string(),
+            event_scheduled: "{name} programó un evento en vivo para {time}: {title}".to_string(),
+            event_scheduled_untimed: "{name} programó un evento en vivo: {title}".to_string(),
+            event_live: "¡{name} está EN VIVO ahora: {title}".to_string(),
+            event_ended: "{title} ha terminado".to_string(),
+            event_recording: "Ya está disponible la grabación de {title}".to_string(),
+            event_untitled: "Evento {id}".to_string(),
+            view_action: "Ver".to_string(),
+            reply_action: "Responder".to_string(),
+        }
+    }
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Self::english()
+    }
+}
+
+/// Declares `Templates` with an override for each of the given messages and
+/// the placeholders it may use, so the two can't drift apart.
+macro_rules! templates {
+    ($($field:ident: [$($placeholder:literal),*]),* $(,)?) => {
+        /// Overrides for the text of each type of notification. Templates use
+        /// the same `{placeholders}` as the message they replace.
+        #[derive(Debug, Clone, Default, Deserialize)]
+        #[serde(default, deny_unknown_fields)]
+        pub struct Templates {
+            $(pub $field: Option<String>,)*
+        }
+
+        impl Templates {
+            pub fn apply(&self, messages: &mut Messages) {
+                $(
+                    if let Some(template) = &self.$field {
+                        messages.$field = template.clone();
+                    }
+                )*
+            }
+
+            /// Describes every placeholder that won't be filled in, so typos
+            /// are caught on startup.
+            pub fn validate(&self) -> Vec<String> {
+                let mut problems = Vec::new();
+                $(
+                    if let Some(template) = &self.$field {
+                        for placeholder in unknown_placeholders(template, &[$($placeholder),*]) {
+                            problems.push(format!(
+                                "templates.{} has unknown placeholder {{{}}}",
+                                stringify!($field),
+                                placeholder
+                            ));
+                        }
+                    }
+                )*
+                problems
+            }
+        }
+    };
+}
+
+templates! {
+    dm_preview: ["sender", "content"],
+    dm: [],
+    dm_digest: ["count"],
+    zap_anonymous: ["amount"],
+    zap_single: ["sender", "amount"],
+    zap_many: ["amount", "count", "top"],
+    summary: ["amount", "count", "top"],
+    summary_anonymous: ["amount"],
+    goal: ["title", "amount", "target"],
+    disconnected: ["duration"],
+    reconnected: [],
+    comment: ["author"],
+    comment_link: ["author", "link"],
+    comment_digest: ["count", "authors"],
+    mention: ["author"],
+    reaction: ["author", "reaction"],
+    repost: ["author"],
+    article: ["author", "title"],
+    article_untitled: ["author"],
+    follow: ["name"],
+    unfollow: ["name"],
+    event_starts: ["title", "duration"],
+    event_scheduled: ["name", "title", "time"],
+    event_scheduled_untimed: ["name", "title"],
+    event_live: ["name", "title"],
+    event_ended: ["title"],
+    event_recording: ["title"],
+}
+
+/// The `{placeholders}` in the template that aren't among the known ones.
+fn unknown_placeholders(template: &str, known: &[&str]) -> Vec<String> {
+    let mut unknown = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let name = &rest[..end];
+        let is_placeholder =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+        if is_placeholder && !known.contains(&name) {
+            unknown.push(name.to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+    unknown
+}
+
+/// Fills in the `{placeholders}` of a message template.
+pub fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut message = template.to_string();
+    for (name, value) in values {
+        message = message.replace(&format!("{{{}}}", name), value);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_replaces_placeholders() {
+        assert_eq!(
+            fill(
+                "{name} zapped {amount}",
+                &[("name", "rob"), ("amount", "21 sats")]
+            ),
+            "rob zapped 21 sats"
+        );
+    }
+
+    #[test]
+    fn templates_report_unknown_placeholders() {
+        let templates = Templates {
+            zap_single: Some("{sender} sent {amout} ⚡".to_string()),
+            follow: Some("{name} followed you {{not a placeholder}}".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            templates.validate(),
+            vec!["templates.zap_single has unknown placeholder {amout}"]
+        );
+    }
+
+    #[test]
+    fn locale_falls_back_to_language() {
+        assert_eq!(
+            Messages::for_locale("es-MX").unwrap().dm_title,
+            Messages::spanish().dm_title
+        );
+        assert!(Messages::for_locale("xx").is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use nostr_sdk::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+
+/// Counters shared by the watcher and notifier tasks.
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug)]
+pub struct Metrics {
+    events_received: AtomicU64,
+    delivery_errors: AtomicU64,
+    relay_reconnects: AtomicU64,
+    events_dropped: AtomicU64,
+    event_channel_near_full: AtomicU64,
+    notifications_queued: AtomicU64,
+    notifications_coalesced: AtomicU64,
+    notifications_dropped: AtomicU64,
+    notifications_sent: Mutex<BTreeMap<&'static str, u64>>,
+    watcher_alive: AtomicBool,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            events_received: AtomicU64::new(0),
+            delivery_errors: AtomicU64::new(0),
+            relay_reconnects: AtomicU64::new(0),
+            events_dropped: AtomicU64::new(0),
+            event_channel_near_full: AtomicU64::new(0),
+            notifications_queued: AtomicU64::new(0),
+            notifications_coalesced: AtomicU64::new(0),
+            notifications_dropped: AtomicU64::new(0),
+            notifications_sent: Mutex::new(BTreeMap::new()),
+            watcher_alive: AtomicBool::new(false),
+        }
+    }
+
+    pub fn event_received(&self) {
+        self.events_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn delivery_error(&self) {
+        self.delivery_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn relay_reconnect(&self) {
+        self.relay_reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_dropped(&self) {
+        self.events_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn event_channel_near_full(&self) {
+        self.event_channel_near_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_queued(&self) {
+        self.notifications_queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_coalesced(&self) {
+        self.notifications_coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_dropped(&self) {
+        self.notifications_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn notification_sent(&self, kind: &'static str) {
+        *self
+            .notifications_sent
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    pub fn set_watcher_alive(&self, alive: bool) {
+        self.watcher_alive.store(alive, Ordering::Relaxed);
+    }
+
+    /// Renders the counters in the Prometheus text format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP bullhorn_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE bullhorn_{} counter", name);
+            let _ = writeln!(out, "bullhorn_{} {}", name, value);
+        };
+        counter(
+            "events_received_total",
+            "Events received from relays.",
+            self.events_received.load(Ordering::Relaxed),
+        );
+        counter(
+            "delivery_errors_total",
+            "Notifications that could not be delivered.",
+            self.delivery_errors.load(Ordering::Relaxed),
+        );
+        counter(
+            "relay_reconnects_total",
+            "Times the relay connection was re-established.",
+            self.relay_reconnects.load(Ordering::Relaxed),
+        );
+        counter(
+            "events_dropped_total",
+            "Low priority events dropped because the notifier fell behind.",
+            self.events_dropped.load(Ordering::Relaxed),
+        );
+        counter(
+            "event_channel_near_full_total",
+            "Times the queue of events waiting on the notifier nearly filled up.",
+            self.event_channel_near_full.load(Ordering::Relaxed),
+        );
+        counter(
+            "notifications_queued_total",
+            "Notifications held back by the rate limiter.",
+            self.notifications_queued.load(Ordering::Relaxed),
+        );
+        counter(
+            "notifications_coalesced_total",
+            "Queued notifications merged into a digest with others of their type.",
+            self.notifications_coalesced.load(Ordering::Relaxed),
+        );
+        counter(
+            "notifications_dropped_total",
+            "Queued notifications dropped because the rate limiter's queue was full.",
+            self.notifications_dropped.load(Ordering::Relaxed),
+        );
+
+        out.push_str("# HELP bullhorn_notifications_sent_total Notifications sent by type.\n");
+        out.push_str("# TYPE bullhorn_notifications_sent_total counter\n");
+        for (kind, count) in self.notifications_sent.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "bullhorn_notifications_sent_total{{type=\"{}\"}} {}",
+                kind, count
+            );
+        }
+
+        out
+    }
+}
+
+/// Serves `/healthz` and `/metrics` on the given port until `shutdown` is
+/// cancelled, dropping any connections still open at that point.
+pub async fn serve_metrics(port: u16, client: Client, shutdown: CancellationToken) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving health and metrics on port {}", port);
+
+    let mut connections = JoinSet::new();
+    loop {
+        select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            Some(_) = connections.join_next() => {}
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let client = client.clone();
+                connections.spawn(async move {
+                    if let Err(err) = handle_connection(stream, &client).await {
+                        debug!("Unable to handle metrics request: {}", err);
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, client: &Client) -> Result<()> {
+    // only the request line matters, so a single read is enough
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await?;
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let (status, body) = match path {
+        "/healthz" if is_healthy(client).await => ("200 OK", "ok\n".to_string()),
+        "/healthz" => ("503 Service Unavailable", "unhealthy\n".to_string()),
+        "/metrics" => ("200 OK", METRICS.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(())
+}
+
+/// Healthy while the watcher is running and at least one relay is connected.
+async fn is_healthy(client: &Client) -> bool {
+    if !METRICS.watcher_alive.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    for relay in client.relays().await.values() {
+        if relay.is_connected().await {
+            return true;
+        }
+    }
+
+    false
+}
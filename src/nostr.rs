@@ -1,12 +1,33 @@
-use std::sync::RwLock;
-use std::time::Duration;
-use std::{collections::HashSet, num::ParseIntError};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::ParseIntError,
+};
 
 use anyhow::{anyhow, bail, Result};
+use futures::future::join_all;
+use humantime::{format_duration, format_rfc3339_seconds};
 use lightning_invoice::{Bolt11Invoice, SignedRawBolt11Invoice};
-use log::{debug, error, info, trace, warn};
+use nostr_sdk::nips::nip59;
 use nostr_sdk::prelude::*;
-use tokio::sync::{broadcast::error::RecvError, mpsc::Sender};
+use serde::Deserialize;
+use tokio::fs::{read_to_string, write, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+use tokio::time::{interval, sleep};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+use crate::metrics::METRICS;
+use crate::relay_info::RelayInfo;
 
 const RELAYS: [&str; 9] = [
     "wss://relay.damus.io",
@@ -20,203 +41,2658 @@ const RELAYS: [&str; 9] = [
     "wss://nostr.bitcoiner.social",
 ];
 
-pub async fn get_client(ndb_path: &str) -> Result<Client> {
+const SEEN_EVENTS_RETENTION: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+const SEEN_EVENTS_CAPACITY: usize = 10_000;
+const SEEN_COORDINATES_CAPACITY: usize = 10_000;
+const MAX_RESUME_LOOKBACK: Duration = Duration::from_secs(60 * 60 * 24);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often checkpoints are written, rather than on every event.
+const CHECKPOINT_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_AUTHOR_NOTE_LOOKBACK_HOURS: u64 = 2 * 24;
+pub const DEFAULT_LIVE_EVENT_LOOKBACK_HOURS: u64 = 24;
+pub const DEFAULT_CLOCK_SKEW_TOLERANCE_SECS: u64 = 60;
+/// How far in the future an event has to be to hint at our clock being off.
+const CLOCK_SKEW_WARNING: Duration = Duration::from_secs(5 * 60);
+/// How many events in a row have to be that far ahead before warning.
+const CLOCK_SKEW_WARNING_EVENTS: u32 = 10;
+pub const DEFAULT_DB_RETENTION_SECS: u64 = DEFAULT_AUTHOR_NOTE_LOOKBACK_HOURS * 60 * 60;
+
+/// NIP-75 zap goals.
+pub const ZAP_GOAL_KIND: u16 = 9041;
+/// NIP-17 lists of the relays someone wants to be sent DMs on.
+const DM_RELAY_LIST_KIND: u16 = 10050;
+/// How long to wait on relays for our NIP-65 relay lists on startup.
+const RELAY_LIST_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait on relays for the zaps to a goal when totaling it.
+const GOAL_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait on relays for the deletion of a comment.
+const DELETION_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_COMMENT_DELETION_GRACE_SECS: u64 = 30;
+pub const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 300;
+
+/// How long to wait on startup for relays to connect.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Startup fails if fewer relays than this connect.
+const MIN_CONNECTED_RELAYS: usize = 1;
+
+/// The `ndb_path` that selects an in-memory database instead of one on disk.
+pub const MEMORY_DB: &str = ":memory:";
+
+/// Builds the client and connects to the relays, at most `max_relays` of them.
+/// Relays are connected to concurrently, and ones that don't connect in time
+/// are left to keep retrying in the background. When `auth_keys` is set, the
+/// client answers NIP-42 AUTH challenges with them so relays that require
+/// authentication will serve events.
+pub async fn get_client(
+    ndb_path: &str,
+    relays: Option<Vec<String>>,
+    max_relays: Option<usize>,
+    auth_keys: Option<&Keys>,
+    proxy: Option<SocketAddr>,
+) -> Result<Client> {
     debug!("Getting nostr client");
-    let db = NdbDatabase::open(ndb_path)?;
-    let client = Client::builder().database(db).build();
-    // add reader relays
-    for relay in RELAYS {
-        client
-            .add_relay_with_opts(relay, RelayOptions::default().write(false))
-            .await?;
+    let builder = if ndb_path == MEMORY_DB {
+        info!("Using an in-memory database. Nothing will be persisted.");
+        // events have to be kept for the comment ownership lookups to work
+        let opts = MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        Client::builder().database(MemoryDatabase::with_opts(opts))
+    } else {
+        Client::builder().database(NdbDatabase::open(ndb_path)?)
+    };
+    let mut opts = Options::new().automatic_authentication(auth_keys.is_some());
+    if let Some(proxy) = proxy {
+        opts = opts.connection(Connection::new().proxy(proxy).target(ConnectionTarget::All));
     }
+    let mut builder = builder.opts(opts);
+    if let Some(keys) = auth_keys {
+        builder = builder.signer(keys.clone());
+    }
+    let client = builder.build();
 
-    client.connect().await;
-    debug!("Nostr client connected to relays");
+    let mut relays = match relays {
+        Some(relays) if !relays.is_empty() => relays,
+        _ => RELAYS.iter().map(ToString::to_string).collect(),
+    };
+    if let Some(max_relays) = max_relays {
+        relays.truncate(max_relays);
+    }
+    // add reader relays. a bad relay is skipped rather than keeping the rest
+    // from being used.
+    let results = join_all(relays.iter().map(|relay| {
+        client.add_relay_with_opts(relay.as_str(), RelayOptions::default().write(false))
+    }))
+    .await;
+    let mut added = 0;
+    for (relay, result) in relays.iter().zip(results) {
+        match result {
+            Ok(_) => added += 1,
+            Err(err) => warn!("Unable to add relay {}: {}", relay, err),
+        }
+    }
+    if added == 0 {
+        bail!("None of the {} relays could be added", relays.len());
+    }
+
+    client.connect_with_timeout(CONNECT_TIMEOUT).await;
+    let mut connected = 0;
+    for (url, relay) in client.relays().await {
+        if relay.is_connected().await {
+            info!("Connected to relay {}", url);
+            connected += 1;
+        } else {
+            warn!(
+                "Unable to connect to relay {}. Retrying in the background.",
+                url
+            );
+        }
+    }
+    if connected < MIN_CONNECTED_RELAYS {
+        bail!("Unable to connect to any relays");
+    }
+    debug!("Nostr client connected to {} relays", connected);
 
     Ok(client)
 }
 
-fn pubkey_receives_filter(pubkey: PublicKey, event_npubs: Vec<PublicKey>) -> Vec<Filter> {
-    vec![
-        // DMs and zaps to our events
-        Filter::new()
-            .kinds([Kind::EncryptedDirectMessage, Kind::ZapReceipt])
-            .pubkey(pubkey)
-            .since(Timestamp::now()),
-        // Events we wrote. This is used when validating responses to ensure
-        // it is a direct response to our notes
-        Filter::new()
-            .kind(Kind::TextNote)
-            .author(pubkey)
-            .since(Timestamp::now() - Duration::from_secs(60 * 60 * 24 * 2)),
-        // Events we are tagged in. This will be paired down to just responses
-        // directly to notes authored by us
-        Filter::new()
-            .kind(Kind::TextNote)
-            .pubkey(pubkey)
-            .since(Timestamp::now()),
-        // Live events from npubs we care about
-        Filter::new()
-            .kind(Kind::LiveEvent)
-            .pubkeys(event_npubs)
-            .since(Timestamp::now() - Duration::from_secs(60 * 60 * 24)),
-    ]
+/// Adds the read relays from the NIP-65 relay lists of our pubkeys, since
+/// that's where others send events tagging us. Relays already connected to
+/// are skipped, and like the rest they're only read from.
+pub async fn add_inbox_relays(client: &Client, pubkeys: &[PublicKey]) -> Result<()> {
+    let filter = Filter::new()
+        .kind(Kind::RelayList)
+        .authors(pubkeys.iter().copied());
+    let source = EventSource::relays(Some(RELAY_LIST_FETCH_TIMEOUT));
+    let relay_lists = client.get_events_of(vec![filter], source).await?;
+
+    for relay_list in newest_by_author(&relay_lists).values() {
+        for relay_url in read_relays(relay_list) {
+            if client
+                .add_relay_with_opts(&relay_url, RelayOptions::default().write(false))
+                .await?
+            {
+                info!(
+                    "Added read relay {} from the relay list of {}",
+                    relay_url,
+                    relay_list.author()
+                );
+            }
+        }
+    }
+    client.connect().await;
+
+    Ok(())
 }
 
-pub async fn watch_pubkey_receives(
-    client: Client,
-    pubkey: PublicKey,
-    event_npubs: Vec<PublicKey>,
-    channel: Sender<Event>,
-) -> Result<()> {
-    let mut notifications = client.notifications();
-    let db = client.database();
+/// Adds the relays from the NIP-17 DM relay lists of our pubkeys, returning
+/// them so gift wrapped DMs can be subscribed to on them specifically.
+pub async fn add_dm_relays(client: &Client, pubkeys: &[PublicKey]) -> Result<Vec<Url>> {
+    let filter = Filter::new()
+        .kind(Kind::from(DM_RELAY_LIST_KIND))
+        .authors(pubkeys.iter().copied());
+    let source = EventSource::relays(Some(RELAY_LIST_FETCH_TIMEOUT));
+    let relay_lists = client.get_events_of(vec![filter], source).await?;
 
-    let filters = pubkey_receives_filter(pubkey, event_npubs.clone());
-    client.subscribe(filters, None).await?;
+    let mut relays = Vec::new();
+    for relay_list in newest_by_author(&relay_lists).values() {
+        for relay_url in dm_relays(relay_list) {
+            if client
+                .add_relay_with_opts(&relay_url, RelayOptions::default().write(false))
+                .await?
+            {
+                info!(
+                    "Added DM relay {} from the DM relay list of {}",
+                    relay_url,
+                    relay_list.author()
+                );
+            }
+            if !relays.contains(&relay_url) {
+                relays.push(relay_url);
+            }
+        }
+    }
+    client.connect().await;
 
-    let events_seen = RwLock::new(HashSet::new());
+    Ok(relays)
+}
 
-    info!("Starting pubkey monitor task.");
+/// Relays may hand back old versions of replaceable events, so only the
+/// newest of each author counts.
+fn newest_by_author(events: &[Event]) -> HashMap<PublicKey, &Event> {
+    let mut newest: HashMap<PublicKey, &Event> = HashMap::new();
+    for event in events {
+        match newest.get(event.author_ref()) {
+            Some(current) if current.created_at() >= event.created_at() => {}
+            _ => {
+                newest.insert(event.author(), event);
+            }
+        }
+    }
+
+    newest
+}
+
+/// The relays in a NIP-17 DM relay list.
+fn dm_relays(relay_list: &Event) -> Vec<Url> {
+    relay_list
+        .tags()
+        .iter()
+        .filter(|tag| tag.kind() == TagKind::Relay)
+        .filter_map(|tag| Url::parse(tag.content()?).ok())
+        .collect()
+}
+
+/// The relays a NIP-65 relay list reads from. Relays without a marker are
+/// used for both reading and writing.
+fn read_relays(relay_list: &Event) -> Vec<Url> {
+    relay_list
+        .tags()
+        .iter()
+        .filter_map(|tag| match tag.as_standardized()? {
+            TagStandard::RelayMetadata {
+                relay_url,
+                metadata: None | Some(RelayMetadata::Read),
+            } => Some(relay_url.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Periodically deletes events older than the retention from the database so
+/// it doesn't grow forever. Only our recent notes are needed, for checking
+/// that replies and zaps are for us.
+pub async fn prune_database(client: Client, retention: Duration, shutdown: CancellationToken) {
+    let db = client.database();
     loop {
-        let (event, relay_url) = match notifications.recv().await {
-            Ok(RelayPoolNotification::Event {
-                event, relay_url, ..
-            }) => (event, relay_url),
-            Err(RecvError::Lagged(n)) => {
-                warn!("Nostr server notifications lagged behind. Skipped {}", n);
-                continue;
+        select! {
+            _ = shutdown.cancelled() => return,
+            _ = sleep(PRUNE_INTERVAL) => {}
+        }
+
+        let until = Timestamp::now() - retention;
+        match db.delete(Filter::new().until(until)).await {
+            Ok(()) => debug!("Pruned events older than {} from the database", until),
+            Err(DatabaseError::NotSupported) => {
+                warn!("The database doesn't support deleting events. Pruning disabled.");
+                return;
             }
-            Err(RecvError::Closed) => {
-                error!("Nostr notifications channel closed suddenly. Exiting pubkey monitor loop.");
-                break;
+            Err(err) => error!("Unable to prune the database: {}", err),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetadataCache {
+    client: Client,
+    ttl: Duration,
+    entries: Arc<RwLock<HashMap<PublicKey, (Instant, Option<Metadata>)>>>,
+}
+
+impl MetadataCache {
+    pub fn new(client: Client, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            entries: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub async fn metadata(&self, pubkey: PublicKey) -> Option<Metadata> {
+        if let Some((fetched_at, metadata)) = self.entries.read().unwrap().get(&pubkey) {
+            if fetched_at.elapsed() < self.ttl {
+                return metadata.clone();
+            }
+        }
+
+        let metadata = match self.client.metadata(pubkey).await {
+            Ok(metadata) => Some(metadata),
+            Err(err) => {
+                debug!("Unable to fetch metadata for {}: {}", pubkey, err);
+                None
             }
-            Ok(RelayPoolNotification::Shutdown) => break,
-            _ => continue,
         };
+        self.entries
+            .write()
+            .unwrap()
+            .insert(pubkey, (Instant::now(), metadata.clone()));
 
-        trace!(
-            "Received event from relay {}: {:?}",
-            relay_url,
-            event.as_json()
-        );
+        metadata
+    }
 
-        let incoming_id = event.id;
-        match event.kind() {
-            Kind::EncryptedDirectMessage | Kind::ZapReceipt => {
-                if let Err(err) = channel.send(*event).await {
-                    error!(
-                        "Unable to send valid event {} on sender channel: {}",
-                        incoming_id, err
-                    );
+    pub async fn picture(&self, pubkey: PublicKey) -> Option<String> {
+        self.metadata(pubkey).await.and_then(|m| m.picture)
+    }
+
+    /// Returns the best human readable name for the pubkey, falling back to a
+    /// shortened npub when no metadata is available.
+    pub async fn display_name(&self, pubkey: PublicKey) -> String {
+        let name = self.metadata(pubkey).await.and_then(|m| {
+            m.display_name
+                .filter(|n| !n.trim().is_empty())
+                .or(m.name.filter(|n| !n.trim().is_empty()))
+        });
+
+        name.unwrap_or_else(|| short_npub(&pubkey))
+    }
+}
+
+/// Event ids we've already notified about. These are persisted to disk so a
+/// restart doesn't re-announce them. Entries are evicted oldest first once
+/// they age out or the capacity is exceeded.
+///
+/// New ids are appended to the file, which is only rewritten once it holds
+/// twice the capacity, so a busy account doesn't rewrite thousands of ids for
+/// every event.
+#[derive(Debug)]
+pub struct SeenEvents {
+    path: PathBuf,
+    capacity: usize,
+    ids: HashSet<EventId>,
+    // ordered oldest first
+    order: VecDeque<(EventId, Timestamp)>,
+    // entries in the file, including evicted ones not yet compacted away
+    persisted: usize,
+}
+
+impl SeenEvents {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut entries = Vec::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some((id, seen_at)) = line.split_once(' ') else {
+                        continue;
+                    };
+                    let (Ok(id), Ok(seen_at)) = (EventId::from_hex(id), seen_at.parse::<u64>())
+                    else {
+                        warn!("Skipping malformed seen event entry: {}", line);
+                        continue;
+                    };
+                    entries.push((id, Timestamp::from(seen_at)));
                 }
             }
-            Kind::TextNote => {
-                let event = event.clone();
-                // We are assuming the first (and probably only) event in the tags
-                // is the event being responded to.
-                let Some(id) = event.event_ids().next() else {
-                    trace!("No event ids found in event {}. Skipping.", event.id);
-                    continue;
-                };
-
-                // Get the event out of the local ndb database. If it's not there, then we haven't seen it
-                // yet. So we are just going to ignore it.
-                let Ok(stored_event) = db.event_by_id(*id).await else {
-                    trace!("Event {} in comment {} not found. Skipping.", id, event.id);
-                    continue;
-                };
-
-                if stored_event.author() != pubkey {
-                    continue;
-                }
-
-                // We wrote the initial note. So the incoming event is a comment
-                // on our note. So we will notify.
-                if let Err(err) = channel.send(*event).await {
-                    error!(
-                        "Unable to send valid event {} on sender channel: {}",
-                        incoming_id, err
-                    );
-                }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let persisted = entries.len();
+        // an id evicted and seen again is appended a second time, and only the
+        // latest entry counts
+        entries.sort_by_key(|(_, seen_at)| *seen_at);
+        let mut ids = HashSet::new();
+        entries.reverse();
+        entries.retain(|(id, _)| ids.insert(*id));
+        entries.reverse();
+
+        let mut seen = Self {
+            path,
+            capacity: SEEN_EVENTS_CAPACITY,
+            ids,
+            order: entries.into(),
+            persisted,
+        };
+        seen.evict();
+        debug!("Loaded {} seen events", seen.order.len());
+
+        Ok(seen)
+    }
+
+    /// Records the event as seen. Returns false if it had already been seen.
+    pub async fn insert(&mut self, id: EventId) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        let seen_at = Timestamp::now();
+        self.order.push_back((id, seen_at));
+        self.evict();
+        let saved = if self.persisted >= self.capacity * 2 {
+            self.save().await
+        } else {
+            self.append(id, seen_at).await
+        };
+        if let Err(err) = saved {
+            warn!("Unable to persist seen events: {}", err);
+        }
+
+        true
+    }
+
+    fn evict(&mut self) {
+        let cutoff = Timestamp::now() - SEEN_EVENTS_RETENTION;
+        while let Some((id, seen_at)) = self.order.front() {
+            if self.order.len() <= self.capacity && *seen_at >= cutoff {
+                break;
+            }
+            self.ids.remove(id);
+            self.order.pop_front();
+        }
+    }
+
+    async fn append(&mut self, id: EventId, seen_at: Timestamp) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{} {}\n", id.to_hex(), seen_at.as_u64()).as_bytes())
+            .await?;
+        self.persisted += 1;
+
+        Ok(())
+    }
+
+    /// Rewrites the file with only the entries still held, dropping evicted
+    /// ones.
+    async fn save(&mut self) -> Result<()> {
+        let contents: String = self
+            .order
+            .iter()
+            .map(|(id, seen_at)| format!("{} {}\n", id.to_hex(), seen_at.as_u64()))
+            .collect();
+        write(&self.path, contents).await?;
+        self.persisted = self.order.len();
+
+        Ok(())
+    }
+}
+
+/// The filters whose `since` is resumed from the last processed event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Checkpoint {
+    Direct,
+    Tagged,
+}
+
+impl Checkpoint {
+    fn for_kind(kind: Kind) -> Option<Self> {
+        match kind {
+            Kind::EncryptedDirectMessage | Kind::ZapReceipt => Some(Self::Direct),
+            Kind::TextNote | Kind::Reaction | Kind::Repost | Kind::GenericRepost => {
+                Some(Self::Tagged)
             }
-            Kind::LiveEvent => {
-                let event_id = event.id();
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Tagged => "tagged",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "direct" => Some(Self::Direct),
+            "tagged" => Some(Self::Tagged),
+            _ => None,
+        }
+    }
+}
+
+/// The timestamp of the most recently processed event per filter, persisted so
+/// events that arrive while we're down are still picked up on the next start.
+/// They're written every `CHECKPOINT_FLUSH_INTERVAL` and on shutdown.
+#[derive(Debug)]
+pub struct Checkpoints {
+    path: PathBuf,
+    last_seen: HashMap<Checkpoint, Timestamp>,
+    // whether there are checkpoints that haven't been written yet
+    dirty: bool,
+}
 
-                if events_seen.read().unwrap().contains(&event_id) {
-                    continue;
+impl Checkpoints {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut last_seen = HashMap::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some((checkpoint, timestamp)) = line.split_once(' ') else {
+                        continue;
+                    };
+                    let (Some(checkpoint), Ok(timestamp)) =
+                        (Checkpoint::parse(checkpoint), timestamp.parse::<u64>())
+                    else {
+                        warn!("Skipping malformed checkpoint entry: {}", line);
+                        continue;
+                    };
+                    last_seen.insert(checkpoint, Timestamp::from(timestamp));
                 }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
 
-                events_seen.write().unwrap().insert(event_id);
-                if let Err(err) = channel.send(*event).await {
-                    error!(
-                        "Unable to send valid event {} on sender channel: {}",
-                        incoming_id, err
-                    );
+        Ok(Self {
+            path,
+            last_seen,
+            dirty: false,
+        })
+    }
+
+    /// Where to resume the filter from. Without a checkpoint we only want new
+    /// events, and we never look back further than `MAX_RESUME_LOOKBACK` to
+    /// avoid a flood of notifications after a long outage.
+    fn since(&self, checkpoint: Checkpoint) -> Timestamp {
+        let now = Timestamp::now();
+        match self.last_seen.get(&checkpoint) {
+            // the since filter is inclusive, so skip the event we already processed
+            Some(last_seen) => (*last_seen + 1).max(now - MAX_RESUME_LOOKBACK).min(now),
+            None => now,
+        }
+    }
+
+    /// Where to resume private DMs from. Gift wraps are timestamped at random,
+    /// so they're fetched with a wide lookback and checked against this once
+    /// the DM inside is unwrapped.
+    pub fn private_dm_since(&self) -> Timestamp {
+        self.since(Checkpoint::Direct)
+    }
+
+    /// Moves the checkpoint past an event once it's been handled. It's only
+    /// written on the next flush.
+    fn record(&mut self, kind: Kind, created_at: Timestamp) {
+        let Some(checkpoint) = Checkpoint::for_kind(kind) else {
+            return;
+        };
+        // don't let events from the future push the checkpoint past now
+        let created_at = created_at.min(Timestamp::now());
+        if self
+            .last_seen
+            .get(&checkpoint)
+            .is_some_and(|last_seen| *last_seen >= created_at)
+        {
+            return;
+        }
+
+        self.last_seen.insert(checkpoint, created_at);
+        self.dirty = true;
+    }
+
+    async fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        match self.save().await {
+            Ok(()) => self.dirty = false,
+            Err(err) => warn!("Unable to persist checkpoints: {}", err),
+        }
+    }
+
+    async fn save(&self) -> Result<()> {
+        let contents: String = self
+            .last_seen
+            .iter()
+            .map(|(checkpoint, timestamp)| {
+                format!("{} {}\n", checkpoint.as_str(), timestamp.as_u64())
+            })
+            .collect();
+        write(&self.path, contents).await?;
+
+        Ok(())
+    }
+}
+
+/// The address of a replaceable event: its kind, author, and `d` tag.
+pub type Coordinate = (Kind, PublicKey, String);
+
+/// The last status seen for each live event. Live events are republished with
+/// a new id whenever they change, so they're tracked by coordinate instead of
+/// by id. Persisted so a restart doesn't re-announce events.
+#[derive(Debug)]
+pub struct LiveEventStatuses {
+    path: PathBuf,
+    statuses: HashMap<Coordinate, (Timestamp, LiveEventStatus)>,
+}
+
+impl LiveEventStatuses {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut statuses = HashMap::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some((coordinate, entry)) = Self::parse_line(line) else {
+                        warn!("Skipping malformed live event entry: {}", line);
+                        continue;
+                    };
+                    statuses.insert(coordinate, entry);
                 }
             }
-            _ => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
         }
+
+        Ok(Self { path, statuses })
     }
 
-    info!("Pubkey monitor task closed.");
-    Ok(())
+    fn parse_line(line: &str) -> Option<(Coordinate, (Timestamp, LiveEventStatus))> {
+        // the d tag is last since it may contain spaces
+        let mut parts = line.splitn(5, ' ');
+        let kind = parts.next()?.parse::<u16>().ok()?;
+        let author = PublicKey::from_hex(parts.next()?).ok()?;
+        let updated_at = parts.next()?.parse::<u64>().ok()?;
+        let status = LiveEventStatus::from(parts.next()?);
+        let identifier = parts.next()?.to_string();
+
+        Some((
+            (Kind::from(kind), author, identifier),
+            (Timestamp::from(updated_at), status),
+        ))
+    }
+
+    /// Records the status of a live event, returning whether it changed.
+    /// Updates older than the one already seen are ignored since relays may
+    /// deliver republished events out of order.
+    pub async fn update(
+        &mut self,
+        coordinate: Coordinate,
+        updated_at: Timestamp,
+        status: LiveEventStatus,
+    ) -> bool {
+        if let Some((last_updated_at, last_status)) = self.statuses.get(&coordinate) {
+            if *last_updated_at >= updated_at || *last_status == status {
+                return false;
+            }
+        }
+
+        self.statuses.insert(coordinate, (updated_at, status));
+        if let Err(err) = self.save().await {
+            warn!("Unable to persist live event statuses: {}", err);
+        }
+
+        true
+    }
+
+    async fn save(&self) -> Result<()> {
+        let contents: String = self
+            .statuses
+            .iter()
+            .map(|((kind, author, identifier), (updated_at, status))| {
+                format!(
+                    "{} {} {} {} {}\n",
+                    kind.as_u16(),
+                    author.to_hex(),
+                    updated_at.as_u64(),
+                    status,
+                    identifier
+                )
+            })
+            .collect();
+        write(&self.path, contents).await?;
+
+        Ok(())
+    }
 }
 
-fn get_zap_request(event: &Event) -> Option<Event> {
-    let Some(tag) = event
-        .tags()
-        .iter()
-        .find(|t| t.kind() == TagKind::Description)
-    else {
-        debug!("no description tag found in event {}", event.id());
-        return None;
-    };
+/// Addressable events that have already been announced, so edits that
+/// republish them under a new id don't notify again.
+///
+/// Like [`SeenEvents`], new coordinates are appended to the file and the
+/// oldest are dropped once over capacity, with the file only rewritten once it
+/// holds twice the capacity.
+#[derive(Debug)]
+pub struct SeenCoordinates {
+    path: PathBuf,
+    capacity: usize,
+    coordinates: HashSet<Coordinate>,
+    // ordered oldest first
+    order: VecDeque<Coordinate>,
+    // entries in the file, including evicted ones not yet compacted away
+    persisted: usize,
+}
 
-    let Ok(event) = Event::from_json(tag.content().unwrap()) else {
-        debug!("description tag is not a valid event");
-        return None;
-    };
-    if let Err(e) = event.verify() {
-        debug!("invalid zap request event: {:?}", e);
-        return None;
+impl SeenCoordinates {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut entries = Vec::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some(coordinate) = Self::parse_line(line) else {
+                        warn!("Skipping malformed coordinate entry: {}", line);
+                        continue;
+                    };
+                    entries.push(coordinate);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let persisted = entries.len();
+        // a coordinate evicted and seen again is appended a second time, and
+        // only the latest entry counts
+        let mut coordinates = HashSet::new();
+        entries.reverse();
+        entries.retain(|coordinate| coordinates.insert(coordinate.clone()));
+        entries.reverse();
+
+        let mut seen = Self {
+            path,
+            capacity: SEEN_COORDINATES_CAPACITY,
+            coordinates,
+            order: entries.into(),
+            persisted,
+        };
+        seen.evict();
+
+        Ok(seen)
+    }
+
+    fn parse_line(line: &str) -> Option<Coordinate> {
+        // the d tag is last since it may contain spaces
+        let mut parts = line.splitn(3, ' ');
+        let kind = parts.next()?.parse::<u16>().ok()?;
+        let author = PublicKey::from_hex(parts.next()?).ok()?;
+        let identifier = parts.next()?.to_string();
+
+        Some((Kind::from(kind), author, identifier))
+    }
+
+    fn format_line((kind, author, identifier): &Coordinate) -> String {
+        format!("{} {} {}\n", kind.as_u16(), author.to_hex(), identifier)
+    }
+
+    /// Records the coordinate, returning whether it's the first time it was
+    /// seen.
+    pub async fn insert(&mut self, coordinate: Coordinate) -> bool {
+        if !self.coordinates.insert(coordinate.clone()) {
+            return false;
+        }
+
+        let line = Self::format_line(&coordinate);
+        self.order.push_back(coordinate);
+        self.evict();
+        let saved = if self.persisted >= self.capacity * 2 {
+            self.save().await
+        } else {
+            self.append(&line).await
+        };
+        if let Err(err) = saved {
+            warn!("Unable to persist seen coordinates: {}", err);
+        }
+
+        true
+    }
+
+    fn evict(&mut self) {
+        while self.order.len() > self.capacity {
+            if let Some(coordinate) = self.order.pop_front() {
+                self.coordinates.remove(&coordinate);
+            }
+        }
     }
 
-    Some(event)
+    async fn append(&mut self, line: &str) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        self.persisted += 1;
+
+        Ok(())
+    }
+
+    /// Rewrites the file with only the coordinates still held, dropping
+    /// evicted ones.
+    async fn save(&mut self) -> Result<()> {
+        let contents: String = self.order.iter().map(Self::format_line).collect();
+        write(&self.path, contents).await?;
+        self.persisted = self.order.len();
+
+        Ok(())
+    }
 }
 
-pub fn get_zap_request_amount(event: &Event) -> Result<u64> {
-    let Some(event) = get_zap_request(event) else {
-        bail!("No zap request present")
-    };
+/// The coordinate of an addressable event, if it has a `d` tag.
+fn coordinate_of(event: &Event) -> Option<Coordinate> {
+    let identifier = event.identifier()?;
+    Some((event.kind(), event.author(), identifier.to_string()))
+}
 
-    match event.tags().iter().find(|t| t.kind() == TagKind::Amount) {
-        Some(tag) => tag
-            .content()
-            .unwrap_or_default()
-            .parse()
-            .map_err(|e: ParseIntError| anyhow!(e)),
-        None => {
-            debug!(
-                "no amount tag found in event {}. will look for an ln invoice",
-                event.id()
-            );
-            let Some(tag) = event.tags().iter().find(|t| t.kind() == TagKind::Bolt11) else {
-                debug!("No bolt11 invoice found in event {}", event.id());
-                bail!("Bolt11 missing from event")
-            };
-            let content = tag.content().unwrap();
-            let signed = content.parse::<SignedRawBolt11Invoice>().unwrap();
-            let Ok(invoice) = Bolt11Invoice::from_signed(signed) else {
-                error!(
-                    "Could not parse the bolt11 tag as a bolt11 invoice: {}",
-                    content
-                );
-                bail!("Bolt11 invoice is invalid")
-            };
+/// How a contact list changed with respect to our pubkeys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowChange {
+    Followed,
+    Unfollowed,
+    Unchanged,
+}
+
+/// Which of our pubkeys each author follows, taken from their latest contact
+/// list. Contact lists are replaced on every change, so this is persisted to be
+/// able to tell when we're newly followed or unfollowed.
+#[derive(Debug)]
+pub struct Followers {
+    path: PathBuf,
+    follows: HashMap<PublicKey, (Timestamp, HashSet<PublicKey>)>,
+}
+
+impl Followers {
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let mut follows = HashMap::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some((author, entry)) = Self::parse_line(line) else {
+                        warn!("Skipping malformed follower entry: {}", line);
+                        continue;
+                    };
+                    follows.insert(author, entry);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(Self { path, follows })
+    }
+
+    fn parse_line(line: &str) -> Option<(PublicKey, (Timestamp, HashSet<PublicKey>))> {
+        let mut parts = line.split(' ');
+        let author = PublicKey::from_hex(parts.next()?).ok()?;
+        let updated_at = parts.next()?.parse::<u64>().ok()?;
+        let followed = parts
+            .next()?
+            .split(',')
+            .map(PublicKey::from_hex)
+            .collect::<Result<_, _>>()
+            .ok()?;
+
+        Some((author, (Timestamp::from(updated_at), followed)))
+    }
+
+    /// Authors currently following at least one of our pubkeys.
+    pub fn authors(&self) -> Vec<PublicKey> {
+        self.follows.keys().copied().collect()
+    }
+
+    /// Records which of our pubkeys the contact list follows and reports how
+    /// that changed since the author's previous contact list.
+    async fn update(&mut self, contacts: &Event, pubkeys: &[PublicKey]) -> FollowChange {
+        let author = contacts.author();
+        let previous = self.follows.get(&author);
+        if previous.is_some_and(|(updated_at, _)| *updated_at >= contacts.created_at()) {
+            return FollowChange::Unchanged;
+        }
+
+        let followed: HashSet<PublicKey> = contacts
+            .public_keys()
+            .filter(|p| pubkeys.contains(p))
+            .copied()
+            .collect();
+        let change = match previous {
+            Some((_, before)) if followed.difference(before).next().is_some() => {
+                FollowChange::Followed
+            }
+            Some((_, before)) if before.difference(&followed).next().is_some() => {
+                FollowChange::Unfollowed
+            }
+            Some(_) => FollowChange::Unchanged,
+            None if followed.is_empty() => FollowChange::Unchanged,
+            None => FollowChange::Followed,
+        };
 
-            Ok(invoice.amount_milli_satoshis().unwrap_or_default())
+        if followed.is_empty() {
+            self.follows.remove(&author);
+        } else {
+            self.follows
+                .insert(author, (contacts.created_at(), followed));
+        }
+        if let Err(err) = self.save().await {
+            warn!("Unable to persist followers: {}", err);
         }
+
+        change
+    }
+
+    async fn save(&self) -> Result<()> {
+        let contents: String = self
+            .follows
+            .iter()
+            .map(|(author, (updated_at, followed))| {
+                let followed: Vec<String> = followed.iter().map(|p| p.to_hex()).collect();
+                format!(
+                    "{} {} {}\n",
+                    author.to_hex(),
+                    updated_at.as_u64(),
+                    followed.join(",")
+                )
+            })
+            .collect();
+        write(&self.path, contents).await?;
+
+        Ok(())
+    }
+}
+
+pub fn short_npub(pubkey: &PublicKey) -> String {
+    let npub = pubkey.to_bech32().unwrap_or_else(|_| pubkey.to_hex());
+    format!("{}…{}", &npub[..12], &npub[npub.len() - 4..])
+}
+
+/// Resolves a NIP-05 address, nprofile, npub, or hex key to a public key.
+pub async fn resolve_pubkey(identifier: &str) -> Result<PublicKey> {
+    let identifier = identifier.trim();
+    if identifier.contains('@') {
+        return nip05::get_key(identifier, None)
+            .await
+            .map_err(|e| anyhow!("unable to resolve NIP-05 address '{}': {}", identifier, e));
+    }
+
+    parse_pubkey(identifier)
+}
+
+/// Parses an npub, nprofile, or hex public key. Unlike `resolve_pubkey`, NIP-05
+/// addresses aren't accepted since they need a network lookup.
+pub fn parse_pubkey(identifier: &str) -> Result<PublicKey> {
+    let identifier = identifier.trim();
+    if identifier.starts_with("nprofile") {
+        let profile = Nip19Profile::from_bech32(identifier)
+            .map_err(|e| anyhow!("invalid nprofile '{}': {}", identifier, e))?;
+        return Ok(profile.public_key);
+    }
+
+    PublicKey::parse(identifier).map_err(|e| anyhow!("invalid public key '{}': {}", identifier, e))
+}
+
+pub fn validate_relay_url(relay: &str) -> Result<()> {
+    let url = Url::parse(relay).map_err(|e| anyhow!("invalid relay url '{}': {}", relay, e))?;
+    match url.scheme() {
+        "ws" | "wss" => Ok(()),
+        scheme => bail!(
+            "invalid relay url '{}': scheme must be ws or wss, got {}",
+            relay,
+            scheme
+        ),
+    }
+}
+
+/// Checks that the proxy is a SOCKS5 url and resolves the address relay
+/// connections should be tunneled through.
+pub fn proxy_socket_addr(proxy: &str) -> Result<SocketAddr> {
+    let url = Url::parse(proxy).map_err(|e| anyhow!("invalid proxy url '{}': {}", proxy, e))?;
+    if !matches!(url.scheme(), "socks5" | "socks5h") {
+        bail!(
+            "invalid proxy url '{}': scheme must be socks5 or socks5h, got {}",
+            proxy,
+            url.scheme()
+        );
+    }
+
+    let (Some(host), Some(port)) = (url.host_str(), url.port()) else {
+        bail!(
+            "invalid proxy url '{}': a host and port are required",
+            proxy
+        );
+    };
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("unable to resolve proxy host '{}'", host))
+}
+
+fn pubkey_receives_filter(
+    pubkeys: Vec<PublicKey>,
+    event_npubs: Vec<PublicKey>,
+    gift_wraps: bool,
+    checkpoints: &Checkpoints,
+    followers: Vec<PublicKey>,
+    lookbacks: &Lookbacks,
+    enabled: &EnabledNotifications,
+) -> Vec<Filter> {
+    // events are only ever fetched a little early, which is fine since
+    // anything already notified about is skipped
+    let skew = lookbacks.clock_skew_tolerance;
+    let now = Timestamp::now() - skew;
+    let mut filters = vec![
+        // Our NIP-51 mute lists, to stop notifying about muted senders
+        Filter::new().kind(Kind::MuteList).authors(pubkeys.clone()),
+    ];
+
+    // DMs and zaps to our events. Zaps also count towards zap goals.
+    let mut direct = Vec::new();
+    if enabled.contains(NotificationKind::Dm) {
+        direct.push(Kind::EncryptedDirectMessage);
+    }
+    if enabled.contains(NotificationKind::Zap) || enabled.contains(NotificationKind::Goal) {
+        direct.push(Kind::ZapReceipt);
+    }
+    if !direct.is_empty() {
+        filters.push(
+            Filter::new()
+                .kinds(direct)
+                .pubkeys(pubkeys.clone())
+                .since(checkpoints.since(Checkpoint::Direct) - skew),
+        );
+    }
+
+    if [
+        NotificationKind::Zap,
+        NotificationKind::Comment,
+        NotificationKind::Reaction,
+        NotificationKind::Repost,
+    ]
+    .into_iter()
+    .any(|kind| enabled.contains(kind))
+    {
+        // Events we wrote. This is used when validating responses to ensure
+        // it is a direct response to our notes
+        filters.push(
+            Filter::new()
+                .kind(Kind::TextNote)
+                .authors(pubkeys.clone())
+                .since(now - lookbacks.author_notes),
+        );
+    }
+
+    // Events we are tagged in. This will be paired down to just responses,
+    // reactions, and reposts directly to notes authored by us. An empty kinds
+    // list would match every kind, so the filter is skipped instead.
+    let mut tagged = Vec::new();
+    if enabled.contains(NotificationKind::Comment) || enabled.contains(NotificationKind::Mention) {
+        tagged.push(Kind::TextNote);
+    }
+    if enabled.contains(NotificationKind::Reaction) {
+        tagged.push(Kind::Reaction);
+    }
+    if enabled.contains(NotificationKind::Repost) {
+        tagged.extend([Kind::Repost, Kind::GenericRepost]);
+    }
+    if !tagged.is_empty() {
+        filters.push(
+            Filter::new()
+                .kinds(tagged)
+                .pubkeys(pubkeys.clone())
+                .since(checkpoints.since(Checkpoint::Tagged) - skew),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Event) {
+        // Live events from npubs we care about
+        filters.push(
+            Filter::new()
+                .kind(Kind::LiveEvent)
+                .pubkeys(event_npubs.clone())
+                .since(now - lookbacks.live_events),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Follow) {
+        // Contact lists following us
+        filters.push(
+            Filter::new()
+                .kind(Kind::ContactList)
+                .pubkeys(pubkeys.clone())
+                .since(now),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Goal) {
+        // Our zap goals, kept in the database to check zaps against
+        filters.push(
+            Filter::new()
+                .kind(Kind::from(ZAP_GOAL_KIND))
+                .authors(pubkeys.clone()),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Article) && !event_npubs.is_empty() {
+        // Long-form articles by npubs we care about. An empty authors list
+        // would match everyone's articles.
+        filters.push(
+            Filter::new()
+                .kind(Kind::LongFormTextNote)
+                .authors(event_npubs)
+                .since(now - Duration::from_secs(60 * 60 * 24)),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Follow) && !followers.is_empty() {
+        // An unfollow drops our p tag, so the contact lists of known followers
+        // have to be watched directly to notice it.
+        filters.push(
+            Filter::new()
+                .kind(Kind::ContactList)
+                .authors(followers)
+                .since(now),
+        );
+    }
+
+    if enabled.contains(NotificationKind::Dm) && gift_wraps {
+        filters.push(gift_wrap_filter(&pubkeys));
+    }
+
+    filters
+}
+
+/// NIP-17 DMs. Gift wraps have their timestamps randomized up to two days in
+/// the past, so we have to look back that far to catch new ones.
+fn gift_wrap_filter(pubkeys: &[PublicKey]) -> Filter {
+    Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkeys(pubkeys.iter().copied())
+        .since(Timestamp::now() - Duration::from_secs(60 * 60 * 24 * 2))
+}
+
+/// Subscribes to gift wraps on the relays from our DM relay lists on their
+/// own, since those relays may only serve DMs.
+async fn subscribe_to_dm_relays(
+    client: &Client,
+    pubkeys: &[PublicKey],
+    dm_relays: &[Url],
+) -> Result<()> {
+    if dm_relays.is_empty() {
+        return Ok(());
+    }
+
+    client
+        .subscribe_to(dm_relays.to_vec(), vec![gift_wrap_filter(pubkeys)], None)
+        .await?;
+    debug!("Subscribed to gift wraps on {} DM relays", dm_relays.len());
+
+    Ok(())
+}
+
+/// The types of notifications that can be turned on and off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Dm,
+    Zap,
+    Comment,
+    Mention,
+    Reaction,
+    Repost,
+    Follow,
+    Event,
+    Article,
+    Goal,
+}
+
+/// Which types of notifications to send. Everything is enabled unless a list
+/// is configured.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct EnabledNotifications(Option<HashSet<NotificationKind>>);
+
+impl EnabledNotifications {
+    pub fn contains(&self, kind: NotificationKind) -> bool {
+        self.0
+            .as_ref()
+            .map_or(true, |enabled| enabled.contains(&kind))
+    }
+}
+
+/// How far back the initial subscription looks for events that were
+/// published before we started.
+#[derive(Debug, Clone)]
+pub struct Lookbacks {
+    /// Our own notes, which replies are checked against.
+    pub author_notes: Duration,
+    /// Live events from the npubs we follow events for.
+    pub live_events: Duration,
+    /// Taken off every `since` so events aren't missed when our clock runs
+    /// ahead of everyone else's.
+    pub clock_skew_tolerance: Duration,
+}
+
+impl Default for Lookbacks {
+    fn default() -> Self {
+        Self {
+            author_notes: Duration::from_secs(DEFAULT_AUTHOR_NOTE_LOOKBACK_HOURS * 60 * 60),
+            live_events: Duration::from_secs(DEFAULT_LIVE_EVENT_LOOKBACK_HOURS * 60 * 60),
+            clock_skew_tolerance: Duration::from_secs(DEFAULT_CLOCK_SKEW_TOLERANCE_SECS),
+        }
+    }
+}
+
+/// Notices when events keep arriving from the future, which means our clock
+/// is behind everyone else's. Events from the past can't tell us anything
+/// since older events are expected when catching up.
+#[derive(Debug, Default)]
+struct ClockSkew {
+    ahead: u32,
+    warned: bool,
+}
+
+impl ClockSkew {
+    /// Returns true the first time enough events in a row were ahead of us.
+    fn observe(&mut self, created_at: Timestamp, now: Timestamp) -> bool {
+        if created_at <= now + CLOCK_SKEW_WARNING {
+            self.ahead = 0;
+            return false;
+        }
+
+        self.ahead += 1;
+        if self.ahead < CLOCK_SKEW_WARNING_EVENTS || self.warned {
+            return false;
+        }
+        self.warned = true;
+        true
+    }
+}
+
+pub const DEFAULT_RECONNECT_MAX_RETRIES: u32 = 10;
+pub const DEFAULT_RECONNECT_MAX_BACKOFF_SECS: u64 = 5 * 60;
+
+#[derive(Debug, Clone)]
+pub struct ReconnectOptions {
+    pub max_retries: u32,
+    pub max_backoff: Duration,
+}
+
+/// Who the watcher subscribes to events for, and the relays it subscribes on.
+#[derive(Debug, Clone)]
+pub struct WatchTargets {
+    /// Whose DMs, zaps, replies and other interactions to watch.
+    pub pubkeys: Vec<PublicKey>,
+    /// Whose live events and articles to watch.
+    pub event_npubs: Vec<PublicKey>,
+    /// The relays the pubkeys take DMs on.
+    pub dm_relays: Vec<Url>,
+    pub relay_info: RelayInfo,
+}
+
+/// What the watcher keeps on disk, so a restart neither repeats
+/// notifications nor misses events that arrived while it was down.
+#[derive(Debug)]
+pub struct WatcherState {
+    pub seen: SeenEvents,
+    pub checkpoints: Checkpoints,
+    pub followers: Followers,
+    pub articles: SeenCoordinates,
+}
+
+/// Which of the watcher's optional features are turned on, and how it
+/// subscribes and reconnects.
+#[derive(Debug, Clone)]
+pub struct WatcherOptions {
+    /// Whether gift wrapped DMs can be read, which takes an nsec.
+    pub gift_wraps: bool,
+    pub notify_unfollows: bool,
+    pub notify_article_edits: bool,
+    /// How long to hold comments in case they're deleted. Zero forwards them
+    /// right away.
+    pub comment_deletion_grace: Duration,
+    pub enabled: EnabledNotifications,
+    pub reconnect: ReconnectOptions,
+    pub lookbacks: Lookbacks,
+}
+
+/// An event that passed the watcher's checks and should be notified about.
+#[derive(Debug, Clone)]
+pub struct ReceivedEvent {
+    pub event: Event,
+    /// The event is a note mentioning us rather than a reply to our note.
+    pub mention: bool,
+    /// The event is a contact list that no longer follows us.
+    pub unfollow: bool,
+    /// The relay the event was received from, used as a hint in links to it.
+    pub relay_url: Option<Url>,
+    /// The event is a zap goal that was reached, with the millisats zapped
+    /// to it so far.
+    pub goal_total_msats: Option<u64>,
+}
+
+impl ReceivedEvent {
+    fn new(event: Event) -> Self {
+        Self {
+            event,
+            mention: false,
+            unfollow: false,
+            relay_url: None,
+            goal_total_msats: None,
+        }
+    }
+
+    fn goal_reached(event: Event, total_msats: u64) -> Self {
+        Self {
+            goal_total_msats: Some(total_msats),
+            ..Self::new(event)
+        }
+    }
+
+    fn unfollow(event: Event) -> Self {
+        Self {
+            unfollow: true,
+            ..Self::new(event)
+        }
+    }
+
+    fn mention(event: Event) -> Self {
+        Self {
+            mention: true,
+            ..Self::new(event)
+        }
+    }
+}
+
+/// Sends the event on to the notifier. When the notifier falls behind, like
+/// during a burst of reactions to a viral note, waiting on it would stall
+/// reading from relays. So reactions and reposts are dropped when the channel
+/// is full, leaving room for DMs, zaps, and everything else, which wait.
+async fn forward(channel: &Sender<ReceivedEvent>, relay_url: &Url, mut received: ReceivedEvent) {
+    received.relay_url = Some(relay_url.clone());
+    let id = received.event.id;
+    let kind = received.event.kind();
+
+    // only logged as the channel crosses the threshold so a burst doesn't
+    // flood the logs
+    if channel.capacity() == channel.max_capacity() / 10 {
+        METRICS.event_channel_near_full();
+        warn!(
+            "The notifier is falling behind. {} of {} queued events are waiting.",
+            channel.max_capacity() - channel.capacity(),
+            channel.max_capacity()
+        );
+    }
+
+    if matches!(kind, Kind::Reaction | Kind::Repost | Kind::GenericRepost) {
+        match channel.try_send(received) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                METRICS.event_dropped();
+                warn!("Event channel is full. Dropping {} event {}.", kind, id);
+            }
+            Err(TrySendError::Closed(_)) => {
+                error!("Unable to send valid event {} on closed sender channel", id)
+            }
+        }
+        return;
+    }
+
+    if let Err(err) = channel.send(received).await {
+        error!(
+            "Unable to send valid event {} on sender channel: {}",
+            id, err
+        );
+    }
+}
+
+pub async fn watch_pubkey_receives(
+    client: Client,
+    targets: WatchTargets,
+    state: WatcherState,
+    options: WatcherOptions,
+    channel: Sender<ReceivedEvent>,
+    tracker: TaskTracker,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let WatchTargets {
+        pubkeys,
+        event_npubs,
+        dm_relays,
+        relay_info,
+    } = targets;
+    let WatcherState {
+        mut seen,
+        mut checkpoints,
+        mut followers,
+        mut articles,
+    } = state;
+    let WatcherOptions {
+        gift_wraps,
+        notify_unfollows,
+        notify_article_edits,
+        comment_deletion_grace,
+        enabled,
+        reconnect: reconnect_options,
+        lookbacks,
+    } = options;
+    let mut notifications = client.notifications();
+    let db = client.database();
+
+    let filters = pubkey_receives_filter(
+        pubkeys.clone(),
+        event_npubs.clone(),
+        gift_wraps,
+        &checkpoints,
+        watched_followers(&followers, notify_unfollows),
+        &lookbacks,
+        &enabled,
+    );
+    log_filters(&filters);
+    relay_info.subscribe(&client, &filters).await?;
+    let dm_relays = if gift_wraps && enabled.contains(NotificationKind::Dm) {
+        dm_relays
+    } else {
+        Vec::new()
+    };
+    subscribe_to_dm_relays(&client, &pubkeys, &dm_relays).await?;
+
+    let mut clock_skew = ClockSkew::default();
+    let mut goal_totals = GoalTotals::default();
+    let mut checkpoint_flush = interval(CHECKPOINT_FLUSH_INTERVAL);
+
+    info!("Starting pubkey monitor task.");
+    METRICS.set_watcher_alive(true);
+    loop {
+        let notification = select! {
+            _ = shutdown.cancelled() => break,
+            _ = checkpoint_flush.tick() => {
+                checkpoints.flush().await;
+                continue;
+            }
+            notification = notifications.recv() => notification,
+        };
+        let (event, relay_url) = match notification {
+            Ok(RelayPoolNotification::Event {
+                event, relay_url, ..
+            }) => (event, relay_url),
+            Err(RecvError::Lagged(n)) => {
+                warn!("Nostr server notifications lagged behind. Skipped {}", n);
+                continue;
+            }
+            Err(RecvError::Closed) => {
+                warn!("Nostr notifications channel closed suddenly. Reconnecting.");
+                let filters = pubkey_receives_filter(
+                    pubkeys.clone(),
+                    event_npubs.clone(),
+                    gift_wraps,
+                    &checkpoints,
+                    watched_followers(&followers, notify_unfollows),
+                    &lookbacks,
+                    &enabled,
+                );
+                log_filters(&filters);
+                if let Err(err) = reconnect(&client, filters, &relay_info, &reconnect_options).await
+                {
+                    error!("{}. Exiting pubkey monitor loop.", err);
+                    break;
+                }
+                if let Err(err) = subscribe_to_dm_relays(&client, &pubkeys, &dm_relays).await {
+                    warn!("Unable to resubscribe to DM relays: {}", err);
+                }
+                notifications = client.notifications();
+                continue;
+            }
+            Ok(RelayPoolNotification::Message { relay_url, message }) => {
+                log_relay_message(&relay_url, &message);
+                continue;
+            }
+            Ok(RelayPoolNotification::Shutdown) => break,
+            _ => continue,
+        };
+
+        let span = info_span!("event", id = %event.id(), kind = %event.kind());
+        let (kind, created_at) = (event.kind(), event.created_at());
+        async {
+            trace!(
+                "Received event from relay {}: {:?}",
+                relay_url,
+                event.as_json()
+            );
+            METRICS.event_received();
+            if clock_skew.observe(event.created_at(), Timestamp::now()) {
+                warn!(
+                    "Events keep arriving timestamped in the future. Your system clock may be \
+                     behind, so check that it's synced with NTP."
+                );
+            }
+
+            match event.kind() {
+                Kind::EncryptedDirectMessage | Kind::GiftWrap => {
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                Kind::ZapReceipt => {
+                    if !is_zap_for(&db, &event, &pubkeys).await {
+                        warn!(
+                            "Zap receipt {} is not for one of our notes. Dropping.",
+                            event.id()
+                        );
+                        return;
+                    }
+
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    let goal = zapped_goal(&db, &event, &pubkeys).await;
+                    forward(&channel, &relay_url, ReceivedEvent::new((*event).clone())).await;
+
+                    let Some(goal) = goal else {
+                        return;
+                    };
+                    let Some(target_msats) = goal_target_msats(&goal) else {
+                        debug!("Zap goal {} has no amount. Skipping.", goal.id());
+                        return;
+                    };
+                    let total_msats = goal_totals.add(&client, &goal, &event).await;
+                    debug!(
+                        "Zap goal {} is at {}/{} millisats",
+                        goal.id(),
+                        total_msats,
+                        target_msats
+                    );
+                    // the goal's own id marks it as announced, so it's only
+                    // notified about once
+                    if total_msats >= target_msats && seen.insert(goal.id()).await {
+                        forward(
+                            &channel,
+                            &relay_url,
+                            ReceivedEvent::goal_reached(goal, total_msats),
+                        )
+                        .await;
+                    }
+                }
+                Kind::TextNote => {
+                    let Some(id) = reply_parent(&event) else {
+                        // Not a reply, so it's only of interest if it mentions us.
+                        if !pubkeys.contains(event.author_ref())
+                            && event.public_keys().any(|p| pubkeys.contains(p))
+                            && seen.insert(event.id()).await
+                        {
+                            forward(&channel, &relay_url, ReceivedEvent::mention(*event)).await;
+                        }
+                        return;
+                    };
+
+                    if !is_authored_by(&db, &id, &pubkeys).await {
+                        trace!(
+                            "Event {} in comment {} is not ours. Skipping.",
+                            id,
+                            event.id
+                        );
+                        return;
+                    }
+
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    // We wrote the initial note. So the incoming event is a comment
+                    // on our note. So we will notify, unless it's deleted first.
+                    if comment_deletion_grace.is_zero() {
+                        forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                        return;
+                    }
+                    // tracked so shutdown waits on comments still in their
+                    // grace window, which are forwarded right away once it starts
+                    tracker.spawn(
+                        forward_unless_deleted(
+                            client.clone(),
+                            *event,
+                            relay_url.clone(),
+                            comment_deletion_grace,
+                            channel.clone(),
+                            shutdown.clone(),
+                        )
+                        .in_current_span(),
+                    );
+                }
+                Kind::Reaction => {
+                    // Per NIP-25, the last e tag is the event being reacted to.
+                    let Some(id) = event.event_ids().last() else {
+                        trace!("No event ids found in reaction {}. Skipping.", event.id);
+                        return;
+                    };
+
+                    if !is_authored_by(&db, id, &pubkeys).await {
+                        trace!(
+                            "Event {} in reaction {} is not ours. Skipping.",
+                            id,
+                            event.id
+                        );
+                        return;
+                    }
+
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                Kind::Repost | Kind::GenericRepost => {
+                    let Some(id) = event.event_ids().next() else {
+                        trace!("No event ids found in repost {}. Skipping.", event.id);
+                        return;
+                    };
+
+                    if !is_authored_by(&db, id, &pubkeys).await {
+                        trace!("Event {} in repost {} is not ours. Skipping.", id, event.id);
+                        return;
+                    }
+
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                Kind::ContactList => match followers.update(&event, &pubkeys).await {
+                    FollowChange::Followed => {
+                        forward(&channel, &relay_url, ReceivedEvent::new(*event)).await
+                    }
+                    FollowChange::Unfollowed if notify_unfollows => {
+                        forward(&channel, &relay_url, ReceivedEvent::unfollow(*event)).await
+                    }
+                    _ => {}
+                },
+                Kind::LongFormTextNote => {
+                    let Some(coordinate) = coordinate_of(&event) else {
+                        trace!("Article {} has no d tag. Skipping.", event.id);
+                        return;
+                    };
+                    // edits republish the article under a new id, so only the
+                    // first version counts as a new article
+                    let first_seen = articles.insert(coordinate).await;
+                    if !seen.insert(event.id()).await || !(first_seen || notify_article_edits) {
+                        return;
+                    }
+
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                // always forwarded since the notifier only keeps the newest one
+                // in memory
+                Kind::MuteList if pubkeys.contains(event.author_ref()) => {
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                // status changes are republished under a new id, so they're
+                // also deduped by coordinate when notifying
+                Kind::LiveEvent => {
+                    if !seen.insert(event.id()).await {
+                        return;
+                    }
+
+                    forward(&channel, &relay_url, ReceivedEvent::new(*event)).await;
+                }
+                _ => {}
+            }
+        }
+        .instrument(span)
+        .await;
+        // only once the event has been forwarded, or dropped, so a crash
+        // before then picks it up again on the next start
+        checkpoints.record(kind, created_at);
+    }
+    checkpoints.flush().await;
+
+    // stop taking in new events. The channel closes when this returns so the
+    // notifier can drain whatever is still queued
+    if shutdown.is_cancelled() {
+        client.unsubscribe_all().await;
+    }
+
+    METRICS.set_watcher_alive(false);
+    info!("Pubkey monitor task closed.");
+    Ok(())
+}
+
+/// Logs the filters being subscribed to at debug level, for working out why an
+/// event isn't matching.
+fn log_filters(filters: &[Filter]) {
+    for line in describe_filters(filters, Timestamp::now()) {
+        debug!("filter: {}", line);
+    }
+}
+
+/// Each filter as JSON, followed by when its `since` is in a readable form.
+fn describe_filters(filters: &[Filter], now: Timestamp) -> Vec<String> {
+    filters
+        .iter()
+        .map(|filter| {
+            let json = filter.as_json();
+            let Some(since) = filter.since else {
+                return json;
+            };
+            format!(
+                "{} (since {}, {} ago)",
+                json,
+                format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(since.as_u64())),
+                format_duration(Duration::from_secs(
+                    now.as_u64().saturating_sub(since.as_u64())
+                ))
+            )
+        })
+        .collect()
+}
+
+/// Logs what relays tell us outside of events. Notices and closed
+/// subscriptions are the only sign of a relay refusing to serve us, so they
+/// stand out rather than looking like nothing is happening.
+fn log_relay_message(relay_url: &Url, message: &RelayMessage) {
+    match message {
+        RelayMessage::Notice { message } => warn!("Notice from relay {}: {}", relay_url, message),
+        RelayMessage::Closed {
+            subscription_id,
+            message,
+        } => {
+            warn!(
+                "Relay {} closed subscription {}: {}",
+                relay_url, subscription_id, message
+            );
+            if message.starts_with("auth-required:") {
+                warn!(
+                    "Relay {} requires authentication. Set nsec and relay_auth to read from it.",
+                    relay_url
+                );
+            }
+        }
+        RelayMessage::Auth { .. } => debug!("Relay {} sent an AUTH challenge", relay_url),
+        RelayMessage::Ok {
+            event_id,
+            status: false,
+            message,
+        } => warn!(
+            "Relay {} rejected event {}: {}",
+            relay_url, event_id, message
+        ),
+        _ => {}
+    }
+}
+
+/// The followers whose contact lists are watched for unfollows.
+fn watched_followers(followers: &Followers, notify_unfollows: bool) -> Vec<PublicKey> {
+    if notify_unfollows {
+        followers.authors()
+    } else {
+        Vec::new()
+    }
+}
+
+async fn reconnect(
+    client: &Client,
+    filters: Vec<Filter>,
+    relay_info: &RelayInfo,
+    options: &ReconnectOptions,
+) -> Result<()> {
+    let mut backoff = Duration::from_secs(1);
+    for attempt in 1..=options.max_retries {
+        sleep(backoff).await;
+        info!(
+            "Reconnecting to relays (attempt {}/{})",
+            attempt, options.max_retries
+        );
+
+        client.connect().await;
+        match relay_info.subscribe(client, &filters).await {
+            Ok(_) => {
+                info!("Reconnected to relays");
+                METRICS.relay_reconnect();
+                return Ok(());
+            }
+            Err(err) => warn!("Unable to resubscribe to relays: {}", err),
+        }
+        backoff = (backoff * 2).min(options.max_backoff);
+    }
+
+    bail!(
+        "Unable to reconnect to relays after {} attempts",
+        options.max_retries
+    )
+}
+
+/// Finds the event being replied to, following NIP-10. Marked e tags are
+/// preferred, using the `reply` marker or falling back to `root`. Otherwise
+/// the deprecated positional scheme is used, where the last e tag is the
+/// parent. Notes that only mention other events have no parent.
+fn reply_parent(event: &Event) -> Option<EventId> {
+    let e_tags: Vec<(EventId, Option<Marker>)> = event
+        .tags()
+        .iter()
+        .filter_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::Event {
+                event_id, marker, ..
+            }) => Some((*event_id, marker.clone())),
+            _ => None,
+        })
+        .collect();
+
+    if e_tags.iter().any(|(_, marker)| marker.is_some()) {
+        let marked = |wanted: Marker| {
+            e_tags
+                .iter()
+                .find(|(_, marker)| marker.as_ref() == Some(&wanted))
+                .map(|(id, _)| *id)
+        };
+        return marked(Marker::Reply).or_else(|| marked(Marker::Root));
+    }
+
+    e_tags.last().map(|(id, _)| *id)
+}
+
+/// Checks whether the event is in the local ndb database and was written by
+/// one of the pubkeys. If it's not there, then we haven't seen it yet, so we
+/// treat it as not ours.
+async fn is_authored_by(db: &Arc<DynNostrDatabase>, id: &EventId, pubkeys: &[PublicKey]) -> bool {
+    match db.event_by_id(*id).await {
+        Ok(event) => pubkeys.contains(event.author_ref()),
+        Err(_) => false,
+    }
+}
+
+/// Checks that the zap request in a receipt was for us. A zapped note must be
+/// one of ours, otherwise the zap must be to one of our profiles.
+async fn is_zap_for(db: &Arc<DynNostrDatabase>, receipt: &Event, pubkeys: &[PublicKey]) -> bool {
+    let Some(request) = get_zap_request(receipt) else {
+        return false;
+    };
+
+    match request.event_ids().next() {
+        Some(id) => is_authored_by(db, &id, pubkeys).await,
+        None => request.public_keys().any(|p| pubkeys.contains(&p)),
+    }
+}
+
+/// The goal a zap receipt is for, if it zapped one of our zap goals.
+async fn zapped_goal(
+    db: &Arc<DynNostrDatabase>,
+    receipt: &Event,
+    pubkeys: &[PublicKey],
+) -> Option<Event> {
+    let request = get_zap_request(receipt)?;
+    let id = request.event_ids().next()?;
+    let event = db.event_by_id(id).await.ok()?;
+
+    (event.kind() == Kind::from(ZAP_GOAL_KIND) && pubkeys.contains(event.author_ref()))
+        .then_some(event)
+}
+
+/// The goal's target in millisats, from its amount tag.
+pub fn goal_target_msats(goal: &Event) -> Option<u64> {
+    goal.tags()
+        .iter()
+        .find(|t| t.kind() == TagKind::Amount)
+        .and_then(|tag| tag.content())
+        .and_then(|amount| amount.parse().ok())
+}
+
+/// The running total of the zaps to each zap goal. A goal's total is seeded
+/// from relays the first time it's zapped, since older zaps may have been
+/// received before we started or pruned from the database. Zaps that arrive
+/// after are added to it, so relays are only asked once per goal.
+#[derive(Debug, Default)]
+struct GoalTotals(HashMap<EventId, GoalTotal>);
+
+#[derive(Debug, Default)]
+struct GoalTotal {
+    /// The receipts already counted, since the ones fetched to seed the total
+    /// may also arrive on the subscription.
+    counted: HashSet<EventId>,
+    msats: u64,
+}
+
+impl GoalTotal {
+    fn add(&mut self, receipt: &Event) {
+        if !self.counted.insert(receipt.id()) {
+            return;
+        }
+        if let Ok(amount_msats) = get_zap_request_amount(receipt) {
+            self.msats = self.msats.saturating_add(amount_msats);
+        }
+    }
+}
+
+impl GoalTotals {
+    /// Counts the zap toward the goal, returning the goal's total.
+    async fn add(&mut self, client: &Client, goal: &Event, receipt: &Event) -> u64 {
+        if !self.0.contains_key(&goal.id()) {
+            if let Some(receipts) = fetch_goal_zaps(client, goal).await {
+                let mut total = GoalTotal::default();
+                for receipt in &receipts {
+                    total.add(receipt);
+                }
+                self.0.insert(goal.id(), total);
+            }
+        }
+
+        match self.0.get_mut(&goal.id()) {
+            Some(total) => {
+                total.add(receipt);
+                total.msats
+            }
+            // the seed is retried on the next zap, so this one is all that's
+            // known for now
+            None => get_zap_request_amount(receipt).unwrap_or_default(),
+        }
+    }
+}
+
+async fn fetch_goal_zaps(client: &Client, goal: &Event) -> Option<Vec<Event>> {
+    let filter = Filter::new().kind(Kind::ZapReceipt).event(goal.id());
+    let source = EventSource::relays(Some(GOAL_FETCH_TIMEOUT));
+    match client.get_events_of(vec![filter], source).await {
+        Ok(receipts) => Some(receipts),
+        Err(err) => {
+            warn!("Unable to fetch zaps to goal {}: {}", goal.id(), err);
+            None
+        }
+    }
+}
+
+/// Holds on to a comment for the grace period before forwarding it, dropping
+/// it if its author deleted it (NIP-09) in the meantime. On shutdown it's
+/// forwarded right away so it isn't lost.
+async fn forward_unless_deleted(
+    client: Client,
+    comment: Event,
+    relay_url: Url,
+    grace: Duration,
+    channel: Sender<ReceivedEvent>,
+    shutdown: CancellationToken,
+) {
+    select! {
+        _ = sleep(grace) => {
+            if is_deleted(&client, &comment).await {
+                info!("Comment {} was deleted by its author. Dropping.", comment.id());
+                return;
+            }
+        }
+        _ = shutdown.cancelled() => {}
+    }
+
+    forward(&channel, &relay_url, ReceivedEvent::new(comment)).await;
+}
+
+/// Whether the event's author has published a deletion for it. Only the
+/// author can delete an event, so deletions from anyone else are ignored.
+async fn is_deleted(client: &Client, event: &Event) -> bool {
+    let filter = Filter::new()
+        .kind(Kind::EventDeletion)
+        .author(event.author())
+        .event(event.id());
+    let source = EventSource::relays(Some(DELETION_FETCH_TIMEOUT));
+    match client.get_events_of(vec![filter], source).await {
+        Ok(deletions) => deletions.iter().any(|deletion| {
+            deletion.author() == event.author() && deletion.event_ids().any(|id| *id == event.id())
+        }),
+        Err(err) => {
+            warn!("Unable to fetch deletions of {}: {}", event.id(), err);
+            false
+        }
+    }
+}
+
+/// A DM decrypted from either a legacy kind 4 event or a NIP-17 gift wrap.
+#[derive(Debug)]
+pub struct DecryptedDm {
+    pub sender: PublicKey,
+    pub content: String,
+    pub created_at: Timestamp,
+}
+
+/// Decrypts a DM with the scheme its kind calls for: NIP-04 for legacy kind 4
+/// DMs and NIP-44 for the seal and gift wrap around NIP-17 ones. Gift wraps
+/// that don't hold a private direct message are `None`.
+pub fn decrypt_dm(keys: &Keys, event: &Event) -> Result<Option<DecryptedDm>> {
+    match event.kind() {
+        Kind::EncryptedDirectMessage => {
+            let content = nip04::decrypt(keys.secret_key()?, event.author_ref(), event.content())?;
+            Ok(Some(DecryptedDm {
+                sender: event.author(),
+                content,
+                created_at: event.created_at(),
+            }))
+        }
+        Kind::GiftWrap => {
+            let gift = nip59::extract_rumor(keys, event)?;
+            if gift.rumor.kind != Kind::PrivateDirectMessage {
+                return Ok(None);
+            }
+
+            Ok(Some(DecryptedDm {
+                sender: gift.sender,
+                content: gift.rumor.content,
+                // the wrapper's timestamp is randomized, so use the rumor's
+                created_at: gift.rumor.created_at,
+            }))
+        }
+        kind => bail!("Kind {} isn't a DM", kind),
+    }
+}
+
+/// Whether zap requests that don't verify are still counted. Set once on
+/// startup from the config.
+static COUNT_UNVERIFIED_ZAPS: AtomicBool = AtomicBool::new(false);
+
+/// Counts zaps whose request doesn't verify, like ones from clients that leave
+/// it unsigned, as long as it can still be read. Their sender and amount can't
+/// be trusted, so this is off by default.
+pub fn count_unverified_zaps(enabled: bool) {
+    if enabled {
+        warn!(
+            "count_unverified_zaps is enabled. Zaps whose request doesn't verify will be \
+             counted, so their sender and amount may be forged."
+        );
+    }
+    COUNT_UNVERIFIED_ZAPS.store(enabled, Ordering::Relaxed);
+}
+
+/// The zap request from a receipt's description tag. It may be unsigned when
+/// unverified zaps are counted, so only the parts we use are kept.
+#[derive(Debug, Clone, PartialEq)]
+struct ZapRequest {
+    author: PublicKey,
+    tags: Vec<Tag>,
+    content: String,
+    verified: bool,
+}
+
+impl ZapRequest {
+    fn event_ids(&self) -> impl Iterator<Item = EventId> + '_ {
+        self.tag_values(Alphabet::E)
+            .filter_map(|id| EventId::from_hex(id).ok())
+    }
+
+    fn public_keys(&self) -> impl Iterator<Item = PublicKey> + '_ {
+        self.tag_values(Alphabet::P)
+            .filter_map(|pubkey| PublicKey::from_hex(pubkey).ok())
+    }
+
+    fn tag_values(&self, letter: Alphabet) -> impl Iterator<Item = &str> {
+        self.tags
+            .iter()
+            .filter(move |t| t.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(letter)))
+            .filter_map(|t| t.content())
+    }
+}
+
+fn get_zap_request(event: &Event) -> Option<ZapRequest> {
+    let Some(tag) = event
+        .tags()
+        .iter()
+        .find(|t| t.kind() == TagKind::Description)
+    else {
+        debug!("no description tag found in event {}", event.id());
+        return None;
+    };
+
+    let Some(description) = tag.content() else {
+        debug!("description tag in event {} is empty", event.id());
+        return None;
+    };
+    parse_zap_request(description, COUNT_UNVERIFIED_ZAPS.load(Ordering::Relaxed))
+}
+
+/// Parses and verifies a zap request. Ones that don't verify, or that aren't
+/// signed at all, are only returned when `accept_unverified` is set.
+fn parse_zap_request(description: &str, accept_unverified: bool) -> Option<ZapRequest> {
+    let request = match Event::from_json(description) {
+        Ok(event) => {
+            let verified = match event.verify() {
+                Ok(()) => true,
+                Err(err) => {
+                    debug!("invalid zap request event: {:?}: {}", err, description);
+                    false
+                }
+            };
+            ZapRequest {
+                author: event.author(),
+                tags: event.tags().to_vec(),
+                content: event.content().to_string(),
+                verified,
+            }
+        }
+        // some clients leave the request unsigned
+        Err(err) => match UnsignedEvent::from_json(description) {
+            Ok(event) => {
+                debug!("zap request is not signed: {}: {}", err, description);
+                ZapRequest {
+                    author: event.pubkey,
+                    tags: event.tags,
+                    content: event.content,
+                    verified: false,
+                }
+            }
+            Err(_) => {
+                debug!(
+                    "description tag is not a valid event: {}: {}",
+                    err, description
+                );
+                return None;
+            }
+        },
+    };
+
+    (request.verified || accept_unverified).then_some(request)
+}
+
+#[derive(Debug, Clone)]
+pub struct Zap {
+    pub sender: Option<PublicKey>,
+    pub amount_msats: u64,
+    pub comment: Option<String>,
+}
+
+pub fn get_zap(event: &Event) -> Result<Zap> {
+    let amount_msats = get_zap_request_amount(event)?;
+    let request = get_zap_request(event);
+    if request.as_ref().is_some_and(|r| !r.verified) {
+        warn!(
+            "Counting zap receipt {} even though its zap request doesn't verify",
+            event.id()
+        );
+    }
+
+    Ok(Zap {
+        sender: request.as_ref().map(|r| r.author),
+        amount_msats,
+        comment: get_zap_request_comment(event),
+    })
+}
+
+/// Returns the comment left with a zap, which is the content of the zap
+/// request, if there is one.
+pub fn get_zap_request_comment(receipt: &Event) -> Option<String> {
+    let request = get_zap_request(receipt)?;
+    let comment = request.content.trim();
+    (!comment.is_empty()).then(|| comment.to_string())
+}
+
+/// Returns the zapped amount in millisats, as specified by NIP-57. The zap
+/// request's amount tag is preferred, falling back to the bolt11 invoice on
+/// the receipt since many clients leave the tag out.
+pub fn get_zap_request_amount(receipt: &Event) -> Result<u64> {
+    let Some(request) = get_zap_request(receipt) else {
+        bail!("No zap request present")
+    };
+
+    let amount = request
+        .tags
+        .iter()
+        .find(|t| t.kind() == TagKind::Amount)
+        .and_then(|tag| tag.content())
+        .map(|amount| amount.parse().map_err(|e: ParseIntError| anyhow!(e)));
+    match amount {
+        Some(Ok(amount)) => return Ok(amount),
+        Some(Err(err)) => debug!(
+            "Invalid amount tag in the zap request of receipt {}: {}. Will look for an ln invoice",
+            receipt.id(),
+            err
+        ),
+        None => debug!(
+            "No amount tag found in the zap request of receipt {}. Will look for an ln invoice",
+            receipt.id()
+        ),
+    }
+
+    let Some(content) = receipt
+        .tags()
+        .iter()
+        .find(|t| t.kind() == TagKind::Bolt11)
+        .and_then(|tag| tag.content())
+    else {
+        debug!("No bolt11 invoice found in zap receipt {}", receipt.id());
+        bail!("Bolt11 missing from zap receipt")
+    };
+    let invoice = content
+        .parse::<SignedRawBolt11Invoice>()
+        .ok()
+        .and_then(|signed| Bolt11Invoice::from_signed(signed).ok());
+    let Some(invoice) = invoice else {
+        error!(
+            "Could not parse the bolt11 tag as a bolt11 invoice: {}",
+            content
+        );
+        bail!("Bolt11 invoice is invalid")
+    };
+
+    invoice
+        .amount_milli_satoshis()
+        .ok_or_else(|| anyhow!("Bolt11 invoice has no amount"))
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_relay_builder::MockRelay;
+
+    use super::*;
+
+    fn id(n: u8) -> EventId {
+        EventId::from_slice(&[n; 32]).unwrap()
+    }
+
+    fn note(tags: &[&[&str]]) -> Event {
+        let tags: Vec<Tag> = tags.iter().map(|t| Tag::parse(t).unwrap()).collect();
+        EventBuilder::new(Kind::TextNote, "hello", tags)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn reply_parent_prefers_reply_marker() {
+        let event = note(&[
+            &["e", &id(1).to_hex(), "", "root"],
+            &["e", &id(2).to_hex(), "", "mention"],
+            &["e", &id(3).to_hex(), "", "reply"],
+        ]);
+
+        assert_eq!(reply_parent(&event), Some(id(3)));
+    }
+
+    #[test]
+    fn reply_parent_falls_back_to_root_marker() {
+        let event = note(&[
+            &["e", &id(1).to_hex(), "", "mention"],
+            &["e", &id(2).to_hex(), "", "root"],
+        ]);
+
+        assert_eq!(reply_parent(&event), Some(id(2)));
+    }
+
+    #[test]
+    fn reply_parent_ignores_mentions() {
+        let event = note(&[&["e", &id(1).to_hex(), "", "mention"]]);
+
+        assert_eq!(reply_parent(&event), None);
+    }
+
+    #[test]
+    fn reply_parent_uses_last_positional_tag() {
+        let event = note(&[&["e", &id(1).to_hex()], &["e", &id(2).to_hex()]]);
+
+        assert_eq!(reply_parent(&event), Some(id(2)));
+    }
+
+    #[test]
+    fn reply_parent_without_e_tags() {
+        let event = note(&[]);
+
+        assert_eq!(reply_parent(&event), None);
+    }
+
+    #[tokio::test]
+    async fn seen_events_evicts_oldest_over_capacity() {
+        let path = std::env::temp_dir().join(format!("bullhorn-seen-{}", uuid::Uuid::new_v4()));
+        let mut seen = SeenEvents::load(path.clone()).await.unwrap();
+        seen.capacity = 3;
+
+        for n in 1..=4 {
+            assert!(seen.insert(id(n)).await);
+        }
+
+        assert!(!seen.ids.contains(&id(1)));
+        assert!((2..=4).all(|n| seen.ids.contains(&id(n))));
+        assert!(!seen.insert(id(4)).await);
+
+        // evicted entries stay in the file until it's compacted
+        let mut reloaded = SeenEvents::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.persisted, 4);
+        reloaded.capacity = 3;
+        reloaded.evict();
+        assert_eq!(reloaded.ids, seen.ids);
+
+        for n in 5..=7 {
+            assert!(seen.insert(id(n)).await);
+        }
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents.lines().count(), 3);
+        let reloaded = SeenEvents::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.ids, seen.ids);
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn seen_coordinates_evict_oldest_over_capacity() {
+        let path =
+            std::env::temp_dir().join(format!("bullhorn-coordinates-{}", uuid::Uuid::new_v4()));
+        let author = Keys::generate().public_key();
+        let coordinate = |n: u8| (Kind::LongFormTextNote, author, format!("article {}", n));
+        let mut seen = SeenCoordinates::load(path.clone()).await.unwrap();
+        seen.capacity = 3;
+
+        for n in 1..=4 {
+            assert!(seen.insert(coordinate(n)).await);
+        }
+
+        assert!(!seen.coordinates.contains(&coordinate(1)));
+        assert!(!seen.insert(coordinate(4)).await);
+
+        // evicted entries stay in the file until it's compacted
+        let mut reloaded = SeenCoordinates::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.persisted, 4);
+        reloaded.capacity = 3;
+        reloaded.evict();
+        assert_eq!(reloaded.coordinates, seen.coordinates);
+
+        // seen again after eviction, so the reload keeps it as the newest
+        assert!(seen.insert(coordinate(1)).await);
+        for n in 5..=6 {
+            assert!(seen.insert(coordinate(n)).await);
+        }
+        let contents = tokio::fs::read_to_string(&path).await.unwrap();
+        assert_eq!(contents.lines().count(), 3);
+        let reloaded = SeenCoordinates::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.order, seen.order);
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[test]
+    fn zap_amount_falls_back_to_receipt_bolt11() {
+        // BOLT 11 test vector for 2500 micro-bitcoin
+        let bolt11 = "lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+        let request = EventBuilder::new(Kind::ZapRequest, "", [])
+            .to_event(&Keys::generate())
+            .unwrap();
+        let receipt = EventBuilder::new(
+            Kind::ZapReceipt,
+            "",
+            [
+                Tag::parse(&["bolt11", bolt11]).unwrap(),
+                Tag::parse(&["description", request.as_json().as_str()]).unwrap(),
+            ],
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+
+        assert_eq!(get_zap_request_amount(&receipt).unwrap(), 250_000_000);
+    }
+
+    #[test]
+    fn verified_zap_requests_are_parsed() {
+        let sender = Keys::generate();
+        let zapped = EventBuilder::text_note("gm", [])
+            .to_event(&Keys::generate())
+            .unwrap()
+            .id();
+        let request = EventBuilder::new(
+            Kind::ZapRequest,
+            "great post",
+            [
+                Tag::event(zapped),
+                Tag::parse(&["amount", "21000"]).unwrap(),
+            ],
+        )
+        .to_event(&sender)
+        .unwrap();
+
+        let parsed = parse_zap_request(&request.as_json(), false).unwrap();
+        assert!(parsed.verified);
+        assert_eq!(parsed.author, sender.public_key());
+        assert_eq!(parsed.content, "great post");
+        assert_eq!(parsed.event_ids().collect::<Vec<_>>(), [zapped]);
+    }
+
+    #[test]
+    fn unverified_zap_requests_are_only_parsed_when_counted() {
+        let sender = Keys::generate();
+        let request = EventBuilder::new(Kind::ZapRequest, "gm", [])
+            .to_event(&sender)
+            .unwrap();
+        let tampered = request.as_json().replace("\"gm\"", "\"gn\"");
+        let unsigned = EventBuilder::new(Kind::ZapRequest, "gm", [])
+            .to_unsigned_event(sender.public_key())
+            .as_json();
+
+        for description in [&tampered, &unsigned] {
+            assert_eq!(parse_zap_request(description, false), None);
+            let parsed = parse_zap_request(description, true).unwrap();
+            assert!(!parsed.verified);
+            assert_eq!(parsed.author, sender.public_key());
+        }
+
+        assert_eq!(parse_zap_request("not a zap request", true), None);
+        assert_eq!(parse_zap_request("{}", true), None);
+    }
+
+    #[test]
+    fn enabled_notifications_default_to_everything() {
+        let everything = EnabledNotifications::default();
+        assert!(everything.contains(NotificationKind::Dm));
+        assert!(everything.contains(NotificationKind::Article));
+
+        let only: EnabledNotifications = serde_json::from_str(r#"["dm", "zap"]"#).unwrap();
+        assert!(only.contains(NotificationKind::Zap));
+        assert!(!only.contains(NotificationKind::Comment));
+    }
+
+    #[test]
+    fn read_relays_skip_write_only_relays() {
+        let tags: Vec<Tag> = [
+            &["r", "wss://both.example.com"][..],
+            &["r", "wss://read.example.com", "read"],
+            &["r", "wss://write.example.com", "write"],
+        ]
+        .iter()
+        .map(|t| Tag::parse(t).unwrap())
+        .collect();
+        let relay_list = EventBuilder::new(Kind::RelayList, "", tags)
+            .to_event(&Keys::generate())
+            .unwrap();
+
+        let relays: Vec<String> = read_relays(&relay_list)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            relays,
+            ["wss://both.example.com/", "wss://read.example.com/"]
+        );
+    }
+
+    #[test]
+    fn goal_target_comes_from_amount_tag() {
+        let goal = |tags: &[&[&str]]| {
+            let tags: Vec<Tag> = tags.iter().map(|t| Tag::parse(t).unwrap()).collect();
+            EventBuilder::new(Kind::from(ZAP_GOAL_KIND), "New mic", tags)
+                .to_event(&Keys::generate())
+                .unwrap()
+        };
+
+        let target = goal(&[&["amount", "210000"], &["relays", "wss://relay.damus.io"]]);
+        assert_eq!(goal_target_msats(&target), Some(210_000));
+        assert_eq!(goal_target_msats(&goal(&[&["amount", "lots"]])), None);
+        assert_eq!(goal_target_msats(&goal(&[])), None);
+    }
+
+    #[test]
+    fn clock_skew_is_warned_about_once_events_keep_arriving_from_the_future() {
+        let now = Timestamp::from(1_700_000_000);
+        let future = now + Duration::from_secs(60 * 60);
+        let mut skew = ClockSkew::default();
+
+        for _ in 1..CLOCK_SKEW_WARNING_EVENTS {
+            assert!(!skew.observe(future, now));
+        }
+        // an event on time resets the count
+        assert!(!skew.observe(now, now));
+        for _ in 1..CLOCK_SKEW_WARNING_EVENTS {
+            assert!(!skew.observe(future, now));
+        }
+        assert!(skew.observe(future, now));
+        assert!(!skew.observe(future, now));
+    }
+
+    #[test]
+    fn dm_relays_come_from_relay_tags() {
+        let tags = [
+            Tag::parse(&["relay", "wss://inbox.example.com"]).unwrap(),
+            Tag::parse(&["relay", "not a url"]).unwrap(),
+            Tag::parse(&["r", "wss://relay.example.com"]).unwrap(),
+        ];
+        let relay_list = EventBuilder::new(Kind::from(DM_RELAY_LIST_KIND), "", tags)
+            .to_event(&Keys::generate())
+            .unwrap();
+
+        assert_eq!(
+            dm_relays(&relay_list),
+            vec![Url::parse("wss://inbox.example.com").unwrap()]
+        );
+    }
+
+    #[test]
+    fn filters_are_described_with_readable_sinces() {
+        let filters = [
+            Filter::new().kind(Kind::TextNote),
+            Filter::new()
+                .kind(Kind::Reaction)
+                .since(Timestamp::from(1_704_067_200)),
+        ];
+
+        let lines = describe_filters(&filters, Timestamp::from(1_704_070_800));
+        assert_eq!(lines[0], filters[0].as_json());
+        assert_eq!(
+            lines[1],
+            format!(
+                "{} (since 2024-01-01T00:00:00Z, 1h ago)",
+                filters[1].as_json()
+            )
+        );
+    }
+
+    #[test]
+    fn dms_are_decrypted_by_kind() {
+        let us = Keys::generate();
+        let them = Keys::generate();
+
+        let content = nip04::encrypt(them.secret_key().unwrap(), &us.public_key(), "gm").unwrap();
+        let legacy = EventBuilder::new(
+            Kind::EncryptedDirectMessage,
+            content,
+            [Tag::public_key(us.public_key())],
+        )
+        .to_event(&them)
+        .unwrap();
+        let dm = decrypt_dm(&us, &legacy).unwrap().unwrap();
+        assert_eq!((dm.sender, dm.content.as_str()), (them.public_key(), "gm"));
+
+        let rumor = EventBuilder::private_msg_rumor(us.public_key(), "gn", None);
+        let wrapped = EventBuilder::gift_wrap(&them, &us.public_key(), rumor, None).unwrap();
+        let dm = decrypt_dm(&us, &wrapped).unwrap().unwrap();
+        assert_eq!((dm.sender, dm.content.as_str()), (them.public_key(), "gn"));
+
+        // NIP-44 is authenticated, so the wrong key is always caught
+        assert!(decrypt_dm(&Keys::generate(), &wrapped).is_err());
+    }
+
+    #[tokio::test]
+    async fn checkpoints_are_written_on_flush() {
+        let path =
+            std::env::temp_dir().join(format!("bullhorn-checkpoints-{}", uuid::Uuid::new_v4()));
+        let mut checkpoints = Checkpoints::load(path.clone()).await.unwrap();
+        let handled_at = Timestamp::now() - Duration::from_secs(60);
+
+        checkpoints.record(Kind::ZapReceipt, handled_at);
+        checkpoints.record(Kind::Reaction, handled_at);
+        assert!(!path.exists());
+
+        checkpoints.flush().await;
+        let reloaded = Checkpoints::load(path.clone()).await.unwrap();
+        assert_eq!(reloaded.last_seen, checkpoints.last_seen);
+        assert_eq!(reloaded.since(Checkpoint::Direct), handled_at + 1);
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn private_dms_sent_while_down_are_kept() {
+        let path =
+            std::env::temp_dir().join(format!("bullhorn-checkpoints-{}", uuid::Uuid::new_v4()));
+        let stopped_at = Timestamp::now() - Duration::from_secs(3600);
+        tokio::fs::write(&path, format!("direct {}\n", stopped_at.as_u64()))
+            .await
+            .unwrap();
+        let checkpoints = Checkpoints::load(path.clone()).await.unwrap();
+
+        let us = Keys::generate();
+        let them = Keys::generate();
+        let sent_at = Timestamp::now() - Duration::from_secs(600);
+        let rumor =
+            EventBuilder::private_msg_rumor(us.public_key(), "gm", None).custom_created_at(sent_at);
+        let wrapped = EventBuilder::gift_wrap(&them, &us.public_key(), rumor, None).unwrap();
+        let dm = decrypt_dm(&us, &wrapped).unwrap().unwrap();
+
+        assert_eq!(dm.created_at, sent_at);
+        assert!(dm.created_at >= checkpoints.private_dm_since());
+        let before = Timestamp::now() - Duration::from_secs(7200);
+        assert!(before < checkpoints.private_dm_since());
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    fn zap_receipt(zapper: &Keys, tags: &[&[&str]]) -> Event {
+        let tags: Vec<Tag> = tags.iter().map(|t| Tag::parse(t).unwrap()).collect();
+        let request = EventBuilder::new(Kind::ZapRequest, "", tags.clone())
+            .to_event(zapper)
+            .unwrap();
+        let mut receipt_tags: Vec<Tag> = tags
+            .into_iter()
+            .filter(|t| t.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::P)))
+            .collect();
+        receipt_tags.push(Tag::parse(&["description", request.as_json().as_str()]).unwrap());
+        EventBuilder::new(Kind::ZapReceipt, "", receipt_tags)
+            .to_event(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn goal_total_counts_each_zap_once() {
+        let zapper = Keys::generate();
+        let first = zap_receipt(&zapper, &[&["amount", "21000"]]);
+        let second = zap_receipt(&zapper, &[&["amount", "5000"]]);
+
+        let mut total = GoalTotal::default();
+        total.add(&first);
+        total.add(&second);
+        // seeded from relays and then delivered on the subscription too
+        total.add(&second);
+
+        assert_eq!(total.msats, 26_000);
+    }
+
+    #[tokio::test]
+    async fn watcher_forwards_only_events_for_us() {
+        let relay = MockRelay::run().await.unwrap();
+        let dir = std::env::temp_dir().join(format!("bullhorn-watch-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let us = Keys::generate();
+        let host = Keys::generate();
+        let stranger = Keys::generate();
+        let me = us.public_key().to_hex();
+
+        let client = get_client(MEMORY_DB, Some(vec![relay.url()]), None, None, None)
+            .await
+            .unwrap();
+        // replies are only forwarded when the note they reply to is ours
+        let our_note = EventBuilder::text_note("gm", []).to_event(&us).unwrap();
+        let their_note = EventBuilder::text_note("gm", [])
+            .to_event(&stranger)
+            .unwrap();
+        client.database().save_event(&our_note).await.unwrap();
+        client.database().save_event(&their_note).await.unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(10);
+        let shutdown = CancellationToken::new();
+        let watcher = tokio::spawn(watch_pubkey_receives(
+            client.clone(),
+            WatchTargets {
+                pubkeys: vec![us.public_key()],
+                event_npubs: vec![host.public_key()],
+                dm_relays: Vec::new(),
+                relay_info: RelayInfo::default(),
+            },
+            WatcherState {
+                seen: SeenEvents::load(dir.join("seen_events")).await.unwrap(),
+                checkpoints: Checkpoints::load(dir.join("checkpoints")).await.unwrap(),
+                followers: Followers::load(dir.join("followers")).await.unwrap(),
+                articles: SeenCoordinates::load(dir.join("articles")).await.unwrap(),
+            },
+            WatcherOptions {
+                gift_wraps: false,
+                notify_unfollows: false,
+                notify_article_edits: false,
+                comment_deletion_grace: Duration::ZERO,
+                enabled: EnabledNotifications::default(),
+                reconnect: ReconnectOptions {
+                    max_retries: 0,
+                    max_backoff: Duration::from_secs(1),
+                },
+                lookbacks: Lookbacks::default(),
+            },
+            sender,
+            TaskTracker::new(),
+            shutdown.clone(),
+        ));
+        sleep(Duration::from_millis(500)).await;
+
+        let zap = zap_receipt(&stranger, &[&["p", &me], &["amount", "21000"]]);
+        let foreign_zap = zap_receipt(&stranger, &[&["p", &me], &["e", &their_note.id().to_hex()]]);
+        let reply = note(&[&["e", &our_note.id().to_hex(), "", "reply"], &["p", &me]]);
+        let foreign_reply = note(&[&["e", &their_note.id().to_hex(), "", "reply"], &["p", &me]]);
+        let live_event = EventBuilder::new(
+            Kind::LiveEvent,
+            "",
+            [
+                Tag::parse(&["d", "stream"]).unwrap(),
+                Tag::parse(&["title", "Live Stream"]).unwrap(),
+                Tag::parse(&["status", "live"]).unwrap(),
+                Tag::parse(&["p", &host.public_key().to_hex(), "", "Host"]).unwrap(),
+            ],
+        )
+        .to_event(&host)
+        .unwrap();
+
+        let publisher = Client::default();
+        publisher.add_relay(relay.url()).await.unwrap();
+        publisher.connect().await;
+        for event in [&zap, &foreign_zap, &reply, &foreign_reply, &live_event] {
+            publisher.send_event(event.clone()).await.unwrap();
+        }
+        sleep(Duration::from_millis(500)).await;
+
+        shutdown.cancel();
+        watcher.await.unwrap().unwrap();
+        let mut forwarded = HashSet::new();
+        while let Some(received) = receiver.recv().await {
+            forwarded.insert(received.event.id());
+        }
+
+        assert_eq!(
+            forwarded,
+            HashSet::from([zap.id(), reply.id(), live_event.id()])
+        );
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
     }
 }
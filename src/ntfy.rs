@@ -1,237 +1,2687 @@
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use humantime::format_duration;
-use log::{debug, error, info};
 use nostr_sdk::prelude::*;
-use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use tokio::fs::{read_to_string, write};
 use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc::{self, Receiver};
+use tokio::task::{AbortHandle, JoinSet};
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
-use crate::nostr::get_zap_request_amount;
+use crate::messages::{fill, Messages};
+use crate::metrics::METRICS;
+use crate::nostr::{
+    decrypt_dm, get_zap, goal_target_msats, short_npub, EnabledNotifications, LiveEventStatuses,
+    MetadataCache, NotificationKind, ReceivedEvent, SeenCoordinates, Zap, ZAP_GOAL_KIND,
+};
+use crate::price::{Fiat, PriceCache};
+use crate::sink::{Action, Notification, NotificationSink};
 
-const API_ENDPOINT: &str = "https://ntfy.sh";
+pub const DEFAULT_SERVER: &str = "https://ntfy.sh";
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_WEB_VIEWER: &str = "https://njump.me";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+// cap any single wait so a misbehaving server can't stall the notifier loop
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 const TITLE: HeaderName = HeaderName::from_static("x-title");
 const PRIORITY: HeaderName = HeaderName::from_static("x-priority");
 const TAGS: HeaderName = HeaderName::from_static("x-tags");
 const CLICK: HeaderName = HeaderName::from_static("x-click");
+const ACTIONS: HeaderName = HeaderName::from_static("x-actions");
+const MARKDOWN: HeaderName = HeaderName::from_static("x-markdown");
+const ICON: HeaderName = HeaderName::from_static("x-icon");
+const AT: HeaderName = HeaderName::from_static("x-at");
+
+const DM_PREVIEW_LENGTH: usize = 100;
+const COMMENT_PREVIEW_LENGTH: usize = 140;
+const ZAP_COMMENT_LENGTH: usize = 140;
+const TITLE_LENGTH: usize = 100;
+/// Well under ntfy's 4096 byte message limit, even for multibyte characters.
+const MESSAGE_LENGTH: usize = 1000;
+const TOP_ZAPPERS: usize = 3;
+/// The most zap comments shown in a single notification.
+const ZAP_COMMENTS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub enum NtfyAuth {
+    Bearer(String),
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+}
 
-const DM_TITLE: HeaderValue = HeaderValue::from_static("New DM Received");
-const ZAPS_TITLE: HeaderValue = HeaderValue::from_static("Zaps Received");
-const COMMENT_TITLE: HeaderValue = HeaderValue::from_static("Comment Received");
-const EVENT_TITLE: HeaderValue = HeaderValue::from_static("Event announcement");
+impl NtfyAuth {
+    /// Picks the auth method from the configured credentials. A bearer token
+    /// takes precedence over a username and password.
+    pub fn new(
+        token: Option<String>,
+        user: Option<String>,
+        password: Option<String>,
+    ) -> Option<Self> {
+        match (token, user) {
+            (Some(token), _) => Some(Self::Bearer(token)),
+            (None, Some(user)) => Some(Self::Basic { user, password }),
+            (None, None) => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct NtfyApiClient {
     api: reqwest::Client,
     endpoint: String,
+    auth: Option<NtfyAuth>,
+    max_retries: u32,
+}
+
+impl NtfyApiClient {
+    pub fn new(
+        api: reqwest::Client,
+        server: Option<&str>,
+        topic: impl ToString,
+        auth: Option<NtfyAuth>,
+    ) -> Self {
+        let server = server.unwrap_or(DEFAULT_SERVER).trim_end_matches('/');
+        Self {
+            api,
+            endpoint: format!("{}/{}", server, topic.to_string()),
+            auth,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sends a single test message, without retries, and returns the status
+    /// the ntfy server responded with.
+    pub async fn send_test(&self, message: &str) -> Result<StatusCode> {
+        let response = self
+            .post()
+            .header(TITLE, "Bullhorn")
+            .header(TAGS, "white_check_mark")
+            .body(message.to_string())
+            .send()
+            .await
+            .with_context(|| format!("Unable to reach ntfy at {}", self.endpoint))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            bail!("ntfy responded with {}: {}", status, body.trim());
+        }
+
+        Ok(status)
+    }
+
+    fn post(&self) -> RequestBuilder {
+        self.authorized(self.api.post(&self.endpoint))
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Some(NtfyAuth::Bearer(token)) => request.bearer_auth(token),
+            Some(NtfyAuth::Basic { user, password }) => request.basic_auth(user, password.as_ref()),
+            None => request,
+        }
+    }
+
+    async fn send_with_retries(&self, request: RequestBuilder) -> Result<Response> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+
+        loop {
+            let Some(attempt_request) = request.try_clone() else {
+                bail!("ntfy request cannot be cloned for sending");
+            };
+
+            let wait = match attempt_request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if attempt < self.max_retries && is_retryable(response.status()) => {
+                    retry_after(&response).unwrap_or(backoff)
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    if status == StatusCode::UNAUTHORIZED {
+                        error!("ntfy server rejected our credentials for {}", self.endpoint);
+                    }
+                    bail!("ntfy responded with {}: {}", status, body.trim())
+                }
+                Err(err)
+                    if attempt < self.max_retries && (err.is_connect() || err.is_timeout()) =>
+                {
+                    backoff
+                }
+                Err(err) if err.is_timeout() => bail!("ntfy request timed out: {}", err),
+                Err(err) => return Err(err.into()),
+            };
+
+            attempt += 1;
+            let wait = wait.min(MAX_BACKOFF);
+            warn!(
+                "ntfy delivery failed. Retrying in {} (attempt {}/{})",
+                format_duration(wait),
+                attempt,
+                self.max_retries
+            );
+            sleep(wait).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    fn request(&self, notification: &Notification) -> RequestBuilder {
+        let mut request = self
+            .post()
+            .header(TITLE, &notification.title)
+            .header(PRIORITY, notification.priority)
+            .header(TAGS, &notification.tags)
+            .body(notification.message.clone());
+        if notification.markdown {
+            request = request.header(MARKDOWN, "yes");
+        }
+        if let Some(click) = &notification.click {
+            request = request.header(CLICK, click);
+        }
+        if !notification.actions.is_empty() {
+            request = request.header(ACTIONS, actions(&notification.actions));
+        }
+        if let Some(at) = notification.at {
+            request = request.header(AT, at.as_u64());
+        }
+        with_icon(request, notification.icon.clone())
+    }
+}
+
+/// What ntfy responds with for a published message. Only the id is needed.
+#[derive(Debug, Deserialize)]
+struct PublishedMessage {
+    id: String,
+}
+
+#[async_trait]
+impl NotificationSink for NtfyApiClient {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        self.send_with_retries(self.request(notification)).await?;
+        Ok(())
+    }
+
+    fn schedules(&self) -> bool {
+        true
+    }
+
+    async fn schedule(&self, notification: &Notification) -> Result<String> {
+        let response = self.send_with_retries(self.request(notification)).await?;
+        let message: PublishedMessage = response.json().await?;
+        Ok(message.id)
+    }
+
+    async fn cancel(&self, id: &str) -> Result<()> {
+        let request = self.authorized(self.api.delete(format!("{}/{}", self.endpoint, id)));
+        self.send_with_retries(request).await?;
+        Ok(())
+    }
+}
+
+/// Formats the ntfy action buttons. Urls are quoted so commas and semicolons
+/// in them don't break the action syntax.
+fn actions(actions: &[Action]) -> String {
+    actions
+        .iter()
+        .map(|action| format!(r#"view, {}, "{}""#, action.label, action.url))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Sets the notification icon, skipping anything that isn't a usable http url
+/// since an invalid header value would fail the whole request.
+fn with_icon(request: RequestBuilder, icon: Option<String>) -> RequestBuilder {
+    let icon = icon
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+        .and_then(|url| HeaderValue::from_str(&url).ok());
+
+    match icon {
+        Some(icon) => request.header(ICON, icon),
+        None => request,
+    }
+}
+
+/// Formats notifications and hands them to a sink for delivery.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    sink: Arc<dyn NotificationSink>,
+    priorities: Priorities,
+    zap_tiers: ZapTiers,
+    web_viewer: String,
+    markdown: bool,
+    dry_run: bool,
+    debug_event_ids: bool,
+    quiet_hours: Option<QuietHours>,
+    messages: Messages,
+    tags: Tags,
+}
+
+impl Notifier {
+    pub fn new(sink: Arc<dyn NotificationSink>) -> Self {
+        Self {
+            sink,
+            priorities: Priorities::default(),
+            zap_tiers: ZapTiers::default(),
+            web_viewer: DEFAULT_WEB_VIEWER.to_string(),
+            markdown: false,
+            dry_run: false,
+            debug_event_ids: false,
+            quiet_hours: None,
+            messages: Messages::default(),
+            tags: Tags::default(),
+        }
+    }
+
+    pub fn with_quiet_hours(mut self, quiet_hours: Option<QuietHours>) -> Self {
+        self.quiet_hours = quiet_hours;
+        self
+    }
+
+    /// Downgrades the priority to the minimum during quiet hours, if they
+    /// apply to the type of notification.
+    fn quiet(&self, kind: NotificationKind, priority: Priority) -> Priority {
+        self.quiet_at(Some(kind), priority, Timestamp::now())
+    }
+
+    /// Like `quiet`, for a notification that's delivered at `at`. Notifications
+    /// without a type, like connectivity alerts, are always quieted.
+    fn quiet_at(
+        &self,
+        kind: Option<NotificationKind>,
+        priority: Priority,
+        at: Timestamp,
+    ) -> Priority {
+        match &self.quiet_hours {
+            Some(quiet)
+                if kind.map_or(true, |kind| quiet.types.contains(kind)) && quiet.contains(at) =>
+            {
+                Priority::Min
+            }
+            _ => priority,
+        }
+    }
+
+    /// Whether reminders can be handed to the sink to deliver when they're
+    /// due, rather than held until then.
+    pub fn schedules_delivery(&self) -> bool {
+        !self.dry_run && self.sink.schedules()
+    }
+
+    /// Appends the id of the event a notification links to onto its message,
+    /// for tracking down why a particular notification was sent.
+    pub fn with_debug_event_ids(mut self, debug_event_ids: bool) -> Self {
+        self.debug_event_ids = debug_event_ids;
+        self
+    }
+
+    /// Logs notifications instead of sending them.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_markdown(mut self, markdown: bool) -> Self {
+        self.markdown = markdown;
+        self
+    }
+
+    pub fn with_web_viewer(mut self, web_viewer: &str) -> Self {
+        self.web_viewer = web_viewer.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Buttons for viewing an entity in the web viewer and replying to it in
+    /// the user's nostr client.
+    fn actions(&self, bech32: &str) -> Vec<Action> {
+        vec![
+            Action {
+                label: self.messages.view_action.clone(),
+                url: format!("{}/{}", self.web_viewer, bech32),
+            },
+            Action {
+                label: self.messages.reply_action.clone(),
+                url: format!("nostr:{}", bech32),
+            },
+        ]
+    }
+
+    pub fn with_zap_tiers(mut self, zap_tiers: ZapTiers) -> Self {
+        self.zap_tiers = zap_tiers;
+        self
+    }
+
+    pub fn with_priorities(mut self, priorities: Priorities) -> Self {
+        self.priorities = priorities;
+        self
+    }
+
+    /// The text to send notifications with, for localizing them.
+    pub fn with_messages(mut self, messages: Messages) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn with_tags(mut self, tags: Tags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    fn notification(
+        &self,
+        kind: &'static str,
+        title: &str,
+        tags: &str,
+        priority: Priority,
+        message: String,
+    ) -> Notification {
+        Notification {
+            kind,
+            title: title.to_string(),
+            // names and previews are user-provided, so the whole message is
+            // cleaned up and capped in one place
+            message: sanitize(&message, MESSAGE_LENGTH),
+            priority,
+            tags: tags.to_string(),
+            markdown: self.markdown,
+            click: None,
+            actions: Vec::new(),
+            icon: None,
+            at: None,
+        }
+    }
+
+    fn with_event_id(&self, mut notification: Notification) -> Notification {
+        if self.debug_event_ids {
+            let entity = notification
+                .click
+                .as_deref()
+                .and_then(|click| click.strip_prefix("nostr:"));
+            if let Some(entity) = entity {
+                notification.message = format!("{}\n\n{}", notification.message, entity);
+            }
+        }
+        notification
+    }
+
+    /// Delivers the notification, recording the outcome in the metrics.
+    async fn send(&self, notification: Notification) -> Result<()> {
+        let notification = self.with_event_id(notification);
+        if self.dry_run {
+            info!(
+                "[dry run] title: {}, priority: {}, tags: {}, click: {}, body: {}",
+                notification.title,
+                notification.priority,
+                notification.tags,
+                notification.click.as_deref().unwrap_or("-"),
+                notification.message
+            );
+            return Ok(());
+        }
+
+        let result = self.sink.deliver(&notification).await;
+        match result {
+            Ok(_) => METRICS.notification_sent(notification.kind),
+            Err(_) => METRICS.delivery_error(),
+        }
+        result
+    }
+
+    /// Hands a notification with `at` set to the sink to deliver when it's
+    /// due, returning the id it can be cancelled by. Only used when
+    /// `schedules_delivery` holds, so never during a dry run.
+    async fn schedule(&self, notification: Notification) -> Result<String> {
+        let notification = self.with_event_id(notification);
+        let result = self.sink.schedule(&notification).await;
+        match result {
+            Ok(_) => METRICS.notification_sent(notification.kind),
+            Err(_) => METRICS.delivery_error(),
+        }
+        result
+    }
+
+    /// Cancels a notification handed to the sink with `schedule`.
+    async fn cancel_scheduled(&self, id: &str) -> Result<()> {
+        self.sink.cancel(id).await
+    }
+
+    pub async fn send_dm_notification(&self, preview: Option<(String, String)>) -> Result<()> {
+        info!("Sending notification about DM");
+        let message = match preview {
+            Some((sender, content)) => fill(
+                &self.messages.dm_preview,
+                &[
+                    ("sender", &escape(&sender, self.markdown)),
+                    ("content", &escape(&content, self.markdown)),
+                ],
+            ),
+            None => self.messages.dm.clone(),
+        };
+
+        let priority = self.quiet(NotificationKind::Dm, self.priorities.dm);
+        let notification = self.notification(
+            "dm",
+            &self.messages.dm_title,
+            &self.tags.dm,
+            priority,
+            message,
+        );
+
+        self.send(notification).await
+    }
+
+    pub async fn send_summary_notification(&self, message: String) -> Result<()> {
+        info!("Sending daily zap summary");
+        let priority = self.quiet(NotificationKind::Zap, self.priorities.zap);
+        let notification = self.notification(
+            "summary",
+            &self.messages.summary_title,
+            &self.tags.zap,
+            priority,
+            message,
+        );
+
+        self.send(notification).await
+    }
+
+    /// Warns that no relays have been connected for the grace period. This is
+    /// sent at high priority, even during quiet hours, since nothing else
+    /// will be notified about until a relay reconnects.
+    pub async fn send_disconnected_notification(&self, down_for: Duration) -> Result<()> {
+        warn!("Sending notification about lost relay connectivity");
+        let message = fill(
+            &self.messages.disconnected,
+            &[("duration", &format_duration(down_for).to_string())],
+        );
+        let notification = self.notification(
+            "connectivity",
+            &self.messages.disconnected_title,
+            "warning",
+            Priority::High,
+            message,
+        );
+
+        self.send(notification).await
+    }
+
+    pub async fn send_reconnected_notification(&self) -> Result<()> {
+        info!("Sending notification about restored relay connectivity");
+        let notification = self.notification(
+            "connectivity",
+            &self.messages.reconnected_title,
+            "white_check_mark",
+            self.quiet_at(None, Priority::Default, Timestamp::now()),
+            self.messages.reconnected.clone(),
+        );
+
+        self.send(notification).await
+    }
+
+    pub async fn send_dm_digest_notification(&self, count: usize) -> Result<()> {
+        info!("Sending notification about {} DMs", count);
+        let message = fill(&self.messages.dm_digest, &[("count", &count.to_string())]);
+
+        let priority = self.quiet(NotificationKind::Dm, self.priorities.dm);
+        let notification = self.notification(
+            "dm",
+            &self.messages.dm_title,
+            &self.tags.dm,
+            priority,
+            message,
+        );
+
+        self.send(notification).await
+    }
+
+    pub async fn send_zap_notification(
+        &self,
+        amount_msats: u64,
+        message: String,
+        icon: Option<String>,
+    ) -> Result<()> {
+        let sats = msats_to_sats(amount_msats);
+        info!("Sending notification about zaps with amount {} sats", sats);
+        let tier = self.zap_tiers.tier(sats);
+        let priority = self
+            .zap_tiers
+            .priority(&tier)
+            .unwrap_or(self.priorities.zap);
+        let tags = self.zap_tiers.tags(&tier).unwrap_or(&self.tags.zap);
+        // big enough zaps are allowed to break through quiet hours
+        let priority = match &self.quiet_hours {
+            Some(quiet) if quiet.breaks_through(sats) => priority,
+            _ => self.quiet(NotificationKind::Zap, priority),
+        };
+
+        let notification = Notification {
+            icon,
+            ..self.notification("zap", &self.messages.zap_title, tags, priority, message)
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_comment_notification(
+        &self,
+        nevent: &str,
+        author: &str,
+        preview: &str,
+        icon: Option<String>,
+    ) -> Result<()> {
+        info!("Sending notification about comment {}", nevent);
+        let mut message = if self.markdown {
+            let link = format!("{}/{}", self.web_viewer, nevent);
+            fill(
+                &self.messages.comment_link,
+                &[("author", &bold(author, true)), ("link", &link)],
+            )
+        } else {
+            fill(&self.messages.comment, &[("author", author)])
+        };
+        if preview.is_empty() {
+            message.push('!');
+        } else {
+            message.push_str(": ");
+            message.push_str(&escape(preview, self.markdown));
+        }
+
+        let priority = self.quiet(NotificationKind::Comment, self.priorities.comment);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", nevent)),
+            actions: self.actions(nevent),
+            icon,
+            ..self.notification(
+                "comment",
+                &self.messages.comment_title,
+                &self.tags.comment,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_comment_digest_notification(
+        &self,
+        count: usize,
+        authors: &[String],
+    ) -> Result<()> {
+        info!("Sending notification about {} comments", count);
+        let authors: Vec<_> = authors
+            .iter()
+            .map(|author| bold(author, self.markdown))
+            .collect();
+        let message = fill(
+            &self.messages.comment_digest,
+            &[
+                ("count", &count.to_string()),
+                ("authors", &authors.join(", ")),
+            ],
+        );
+
+        let priority = self.quiet(NotificationKind::Comment, self.priorities.comment);
+        let notification = self.notification(
+            "comment",
+            &self.messages.comment_title,
+            &self.tags.comment,
+            priority,
+            message,
+        );
+
+        self.send(notification).await
+    }
+
+    pub async fn send_mention_notification(&self, nevent: &str, author: &str) -> Result<()> {
+        info!("Sending notification about mention {}", nevent);
+        let message = fill(
+            &self.messages.mention,
+            &[("author", &escape(author, self.markdown))],
+        );
+
+        let priority = self.quiet(NotificationKind::Mention, self.priorities.mention);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", nevent)),
+            ..self.notification(
+                "mention",
+                &self.messages.mention_title,
+                &self.tags.mention,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_reaction_notification(
+        &self,
+        nevent: &str,
+        author: &str,
+        reaction: &str,
+    ) -> Result<()> {
+        info!("Sending notification about reaction {}", nevent);
+        // an empty reaction is treated as a like per NIP-25
+        let reaction = match reaction.trim() {
+            "" => "+",
+            reaction => reaction,
+        };
+        let message = fill(
+            &self.messages.reaction,
+            &[
+                ("author", &escape(author, self.markdown)),
+                ("reaction", &escape(reaction, self.markdown)),
+            ],
+        );
+
+        let priority = self.quiet(NotificationKind::Reaction, self.priorities.reaction);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", nevent)),
+            ..self.notification(
+                "reaction",
+                &self.messages.reaction_title,
+                &self.tags.reaction,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_repost_notification(&self, nevent: &str, author: &str) -> Result<()> {
+        info!("Sending notification about repost of {}", nevent);
+        let message = fill(
+            &self.messages.repost,
+            &[("author", &escape(author, self.markdown))],
+        );
+
+        let priority = self.quiet(NotificationKind::Repost, self.priorities.repost);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", nevent)),
+            ..self.notification(
+                "repost",
+                &self.messages.repost_title,
+                &self.tags.repost,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_goal_reached_notification(
+        &self,
+        nevent: &str,
+        title: &str,
+        target_msats: u64,
+        total_msats: u64,
+    ) -> Result<()> {
+        info!("Sending notification about reached zap goal {}", nevent);
+        let message = fill(
+            &self.messages.goal,
+            &[
+                (
+                    "title",
+                    &bold(&sanitize(title, TITLE_LENGTH), self.markdown),
+                ),
+                ("amount", &format_amount(total_msats, &self.messages, None)),
+                ("target", &format_amount(target_msats, &self.messages, None)),
+            ],
+        );
+
+        let priority = self.quiet(NotificationKind::Goal, self.priorities.goal);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", nevent)),
+            actions: self.actions(nevent),
+            ..self.notification(
+                "goal",
+                &self.messages.goal_title,
+                &self.tags.goal,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_article_notification(
+        &self,
+        naddr: &str,
+        title: Option<&str>,
+        author: &str,
+    ) -> Result<()> {
+        info!("Sending notification about article {}", naddr);
+        let author = escape(author, self.markdown);
+        let author = author.as_str();
+        let message = match title {
+            Some(title) => fill(
+                &self.messages.article,
+                &[
+                    ("author", author),
+                    (
+                        "title",
+                        &bold(&sanitize(title, TITLE_LENGTH), self.markdown),
+                    ),
+                ],
+            ),
+            None => fill(&self.messages.article_untitled, &[("author", author)]),
+        };
+
+        let priority = self.quiet(NotificationKind::Article, self.priorities.article);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", naddr)),
+            actions: self.actions(naddr),
+            ..self.notification(
+                "article",
+                &self.messages.article_title,
+                &self.tags.article,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    pub async fn send_follow_notification(
+        &self,
+        follower: PublicKey,
+        name: &str,
+        unfollowed: bool,
+    ) -> Result<()> {
+        let npub = follower
+            .to_bech32()
+            .context("Unable to encode follower npub")?;
+        info!(
+            "Sending notification about {} {}",
+            if unfollowed {
+                "unfollow by"
+            } else {
+                "follow by"
+            },
+            npub
+        );
+        let name = escape(name, self.markdown);
+        let name = name.as_str();
+        let (title, message) = if unfollowed {
+            (
+                &self.messages.unfollow_title,
+                fill(&self.messages.unfollow, &[("name", name)]),
+            )
+        } else {
+            (
+                &self.messages.follow_title,
+                fill(&self.messages.follow, &[("name", name)]),
+            )
+        };
+
+        let priority = self.quiet(NotificationKind::Follow, self.priorities.follow);
+        let notification = Notification {
+            click: Some(format!("nostr:{}", npub)),
+            ..self.notification("follow", title, &self.tags.follow, priority, message)
+        };
+
+        self.send(notification).await
+    }
+
+    /// Announces a change in a live event's status. Going live is the urgent
+    /// one, so it's sent with its own, higher, priority.
+    pub async fn send_event_notification(
+        &self,
+        naddr: &str,
+        event: &LiveEvent,
+        host: &str,
+    ) -> Result<()> {
+        let title = self.event_title(event);
+        let host = escape(host, self.markdown);
+        let host = host.as_str();
+        let (message, priority) = match event.status {
+            Some(LiveEventStatus::Planned) => {
+                let message = match event.starts {
+                    Some(starts) => fill(
+                        &self.messages.event_scheduled,
+                        &[
+                            ("name", host),
+                            ("title", &title),
+                            ("time", &format_event_time(starts)),
+                        ],
+                    ),
+                    None => fill(
+                        &self.messages.event_scheduled_untimed,
+                        &[("name", host), ("title", &title)],
+                    ),
+                };
+                (message, self.priorities.event)
+            }
+            Some(LiveEventStatus::Ended) => (
+                fill(&self.messages.event_ended, &[("title", &title)]),
+                self.priorities.event,
+            ),
+            _ => (
+                fill(
+                    &self.messages.event_live,
+                    &[("name", host), ("title", &title)],
+                ),
+                self.priorities.event_live,
+            ),
+        };
+
+        info!("Sending notification about live event {}", naddr);
+        let notification = self.event_notification(
+            naddr,
+            message,
+            self.quiet(NotificationKind::Event, priority),
+        );
+        self.send(notification).await
+    }
+
+    /// Reminds that a planned live event is about to start. When `at` is set,
+    /// the sink holds the reminder and delivers it then instead of right away.
+    pub async fn send_event_reminder(&self, naddr: &str, event: &LiveEvent) -> Result<()> {
+        info!("Sending reminder about live event {}", naddr);
+        let notification = self.event_reminder(naddr, event, Timestamp::now());
+        self.send(notification).await
+    }
+
+    /// Has the sink deliver a reminder about the event at `at`, returning the
+    /// id it can be cancelled by.
+    pub async fn schedule_event_reminder(
+        &self,
+        naddr: &str,
+        event: &LiveEvent,
+        at: Timestamp,
+    ) -> Result<String> {
+        info!("Scheduling reminder about live event {} for {}", naddr, at);
+        let notification = Notification {
+            at: Some(at),
+            ..self.event_reminder(naddr, event, at)
+        };
+        self.schedule(notification).await
+    }
+
+    /// A reminder about the event, worded and quieted for when it's delivered.
+    fn event_reminder(
+        &self,
+        naddr: &str,
+        event: &LiveEvent,
+        delivered_at: Timestamp,
+    ) -> Notification {
+        let starts_in = event.starts.map_or(0, |starts| {
+            starts.as_u64().saturating_sub(delivered_at.as_u64())
+        });
+        let message = fill(
+            &self.messages.event_starts,
+            &[
+                ("title", &self.event_title(event)),
+                (
+                    "duration",
+                    &format_duration(Duration::from_secs(starts_in)).to_string(),
+                ),
+            ],
+        );
+
+        let priority = self.quiet_at(
+            Some(NotificationKind::Event),
+            self.priorities.event,
+            delivered_at,
+        );
+        self.event_notification(naddr, message, priority)
+    }
+
+    fn event_notification(&self, naddr: &str, message: String, priority: Priority) -> Notification {
+        Notification {
+            click: Some(format!("nostr:{}", naddr)),
+            actions: self.actions(naddr),
+            ..self.notification(
+                "event",
+                &self.messages.event_title,
+                &self.tags.event,
+                priority,
+                message,
+            )
+        }
+    }
+
+    pub async fn send_recording_notification(
+        &self,
+        naddr: &str,
+        event: &LiveEvent,
+        recording: &str,
+    ) -> Result<()> {
+        info!(
+            "Sending notification about recording of live event {}",
+            naddr
+        );
+        let message = fill(
+            &self.messages.event_recording,
+            &[("title", &self.event_title(event))],
+        );
+
+        let priority = self.quiet(NotificationKind::Event, self.priorities.event);
+        let notification = Notification {
+            click: Some(recording.to_string()),
+            actions: self.actions(naddr),
+            ..self.notification(
+                "event",
+                &self.messages.event_title,
+                &self.tags.event,
+                priority,
+                message,
+            )
+        };
+
+        self.send(notification).await
+    }
+
+    fn event_title(&self, event: &LiveEvent) -> String {
+        match &event.title {
+            Some(title) => escape(&sanitize(title, TITLE_LENGTH), self.markdown),
+            None => fill(
+                &self.messages.event_untitled,
+                &[("id", &escape(&event.id, self.markdown))],
+            ),
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(Duration::from_secs(seconds))
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Min = 1,
+    Low = 2,
+    Default = 3,
+    High = 4,
+    Max = 5,
+}
+
+/// The priority to send each type of notification with.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Priorities {
+    pub dm: Priority,
+    pub zap: Priority,
+    pub comment: Priority,
+    pub mention: Priority,
+    pub event: Priority,
+    /// Used instead of `event` when a live event starts.
+    pub event_live: Priority,
+    pub reaction: Priority,
+    pub repost: Priority,
+    pub follow: Priority,
+    pub article: Priority,
+    pub goal: Priority,
+}
+
+impl Default for Priorities {
+    fn default() -> Self {
+        Self {
+            dm: Priority::Default,
+            zap: Priority::Default,
+            comment: Priority::Default,
+            mention: Priority::Default,
+            event: Priority::Default,
+            event_live: Priority::High,
+            reaction: Priority::Default,
+            repost: Priority::Default,
+            follow: Priority::Default,
+            article: Priority::Default,
+            goal: Priority::Default,
+        }
+    }
+}
+
+/// Custom titles for each type of notification, replacing the locale's.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Titles {
+    pub dm: Option<String>,
+    pub zap: Option<String>,
+    pub comment: Option<String>,
+    pub mention: Option<String>,
+    pub event: Option<String>,
+    pub reaction: Option<String>,
+    pub repost: Option<String>,
+    pub follow: Option<String>,
+    pub unfollow: Option<String>,
+    pub article: Option<String>,
+    pub goal: Option<String>,
+}
+
+impl Titles {
+    pub fn apply(&self, messages: &mut Messages) {
+        if let Some(title) = &self.dm {
+            messages.dm_title = title.clone();
+        }
+        if let Some(title) = &self.zap {
+            messages.zap_title = title.clone();
+        }
+        if let Some(title) = &self.comment {
+            messages.comment_title = title.clone();
+        }
+        if let Some(title) = &self.mention {
+            messages.mention_title = title.clone();
+        }
+        if let Some(title) = &self.event {
+            messages.event_title = title.clone();
+        }
+        if let Some(title) = &self.reaction {
+            messages.reaction_title = title.clone();
+        }
+        if let Some(title) = &self.repost {
+            messages.repost_title = title.clone();
+        }
+        if let Some(title) = &self.follow {
+            messages.follow_title = title.clone();
+        }
+        if let Some(title) = &self.unfollow {
+            messages.unfollow_title = title.clone();
+        }
+        if let Some(title) = &self.article {
+            messages.article_title = title.clone();
+        }
+        if let Some(title) = &self.goal {
+            messages.goal_title = title.clone();
+        }
+    }
+}
+
+/// The ntfy tags of each type of notification. Tags that match an emoji
+/// shortcode are shown as that emoji. Unfollows use the follow tags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Tags {
+    pub dm: String,
+    pub zap: String,
+    pub comment: String,
+    pub mention: String,
+    pub event: String,
+    pub reaction: String,
+    pub repost: String,
+    pub follow: String,
+    pub article: String,
+    pub goal: String,
+}
+
+impl Default for Tags {
+    fn default() -> Self {
+        Self {
+            dm: "book".to_string(),
+            zap: "moneybag".to_string(),
+            comment: "incoming_envelope".to_string(),
+            mention: "speech_balloon".to_string(),
+            event: "spiral_calendar".to_string(),
+            reaction: "heart".to_string(),
+            repost: "repeat".to_string(),
+            follow: "bust_in_silhouette".to_string(),
+            article: "newspaper".to_string(),
+            goal: "dart".to_string(),
+        }
+    }
+}
+
+/// Sats thresholds splitting zap notifications into tiers. Small and large
+/// zaps are sent with their tier's priority, low and max by default, and
+/// everything in between uses the configured zap priority. Any tier can also
+/// be given its own tags, keyed by its name, otherwise it uses the zap tags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ZapTiers {
+    pub small_below_sats: u64,
+    pub large_above_sats: u64,
+    pub small_priority: Priority,
+    pub large_priority: Priority,
+    pub tags: HashMap<ZapTier, String>,
+}
+
+impl Default for ZapTiers {
+    fn default() -> Self {
+        Self {
+            small_below_sats: 1_000,
+            large_above_sats: 100_000,
+            small_priority: Priority::Low,
+            large_priority: Priority::Max,
+            tags: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZapTier {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ZapTiers {
+    fn tier(&self, sats: u64) -> ZapTier {
+        if sats < self.small_below_sats {
+            ZapTier::Small
+        } else if sats > self.large_above_sats {
+            ZapTier::Large
+        } else {
+            ZapTier::Medium
+        }
+    }
+
+    /// The tier's own priority. Medium zaps have none and use the zap
+    /// priority.
+    fn priority(&self, tier: &ZapTier) -> Option<Priority> {
+        match tier {
+            ZapTier::Small => Some(self.small_priority),
+            ZapTier::Medium => None,
+            ZapTier::Large => Some(self.large_priority),
+        }
+    }
+
+    fn tags(&self, tier: &ZapTier) -> Option<&String> {
+        self.tags.get(tier)
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Max => write!(f, "max"),
+            Self::High => write!(f, "high"),
+            Self::Default => write!(f, "default"),
+            Self::Low => write!(f, "low"),
+            Self::Min => write!(f, "min"),
+        }
+    }
+}
+
+impl TryFrom<Priority> for HeaderValue {
+    type Error = http::Error;
+
+    fn try_from(value: Priority) -> std::result::Result<Self, Self::Error> {
+        let n = (value as u8).to_string();
+        Ok(HeaderValue::from_str(&n)?)
+    }
+}
+
+/// A daily window in which notifications are sent at the minimum priority so
+/// they don't buzz the phone. The window may wrap past midnight.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub timezone: Tz,
+    /// Zaps of at least this many sats are still sent at their usual priority.
+    pub break_through_sats: Option<u64>,
+    /// The types of notifications that are quieted. All of them unless a list
+    /// is configured.
+    #[serde(default)]
+    pub types: EnabledNotifications,
+}
+
+impl QuietHours {
+    fn contains(&self, at: Timestamp) -> bool {
+        let Some(at) = DateTime::from_timestamp(at.as_u64() as i64, 0) else {
+            return false;
+        };
+        let time = at.with_timezone(&self.timezone).time();
+
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    fn breaks_through(&self, sats: u64) -> bool {
+        self.break_through_sats.is_some_and(|min| sats >= min)
+    }
+}
+
+pub const DEFAULT_ZAP_AGGREGATION_SECS: u64 = 2 * 60;
+
+#[derive(Debug, Clone)]
+pub struct ZapOptions {
+    /// Zaps below this many sats are ignored.
+    pub min_sats: Option<u64>,
+    /// How long to wait for more zaps before notifying. Zero notifies on
+    /// every zap.
+    pub window: Duration,
+    /// Converts zap amounts to an approximate fiat value when set.
+    pub price: Option<PriceCache>,
+    pub daily_summary: DailySummary,
+}
+
+impl ZapOptions {
+    fn below_min(&self, amount_msats: u64) -> bool {
+        self.min_sats
+            .is_some_and(|min| msats_to_sats(amount_msats) < min)
+    }
+}
+
+/// A once a day roll-up of the day's zaps, sent at a time of day in the given
+/// IANA timezone.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DailySummary {
+    pub enabled: bool,
+    pub time: NaiveTime,
+    pub timezone: Tz,
+}
+
+impl Default for DailySummary {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+impl DailySummary {
+    /// How long from `now` until the next summary is due.
+    fn until_next(&self, now: DateTime<Utc>) -> Duration {
+        let local = now.with_timezone(&self.timezone);
+        let mut date = local.date_naive();
+        loop {
+            // a time skipped by a DST change falls back to the next day
+            let next = self
+                .timezone
+                .from_local_datetime(&date.and_time(self.time))
+                .earliest();
+            if let Some(next) = next.filter(|next| *next > local) {
+                return (next.with_timezone(&Utc) - now)
+                    .to_std()
+                    .unwrap_or_default();
+            }
+            date = date.succ_opt().unwrap_or(date);
+        }
+    }
+}
+
+pub const DEFAULT_EVENT_REMINDER_OFFSETS_MINS: [u64; 1] = [30];
+
+pub const DEFAULT_DM_AGGREGATION_SECS: u64 = 0;
+pub const DEFAULT_COMMENT_AGGREGATION_SECS: u64 = 0;
+
+/// How long to roll up DMs and comments into a single digest notification.
+/// Zero notifies about each one immediately.
+#[derive(Debug, Clone, Default)]
+pub struct DigestOptions {
+    pub dm_window: Duration,
+    pub comment_window: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LiveEventOptions {
+    /// Also notify when an event is announced as planned, not just when it
+    /// goes live.
+    pub notify_planned: bool,
+    /// Also notify when an event ends.
+    pub notify_ended: bool,
+    /// Notify when a recording of an event is published.
+    pub notify_recordings: bool,
+    /// How long before a planned event starts to send each reminder.
+    pub reminder_offsets: Vec<Duration>,
+}
+
+/// How the notifier batches and filters what the watcher sends it.
+#[derive(Debug, Clone)]
+pub struct NotifierOptions {
+    pub zaps: ZapOptions,
+    pub digests: DigestOptions,
+    pub live_events: LiveEventOptions,
+    /// Who to drop notifications from. Mute lists are added as they arrive.
+    pub senders: SenderFilter,
+    pub enabled: EnabledNotifications,
+}
+
+/// What's tracked about live events between notifications, all of it
+/// persisted so a restart doesn't repeat announcements or lose reminders.
+#[derive(Debug)]
+pub struct LiveEventState {
+    /// The last status seen for each event.
+    pub statuses: LiveEventStatuses,
+    /// Events whose recording was already announced.
+    pub recordings: SeenCoordinates,
+    pub reminders: Reminders,
+}
+
+impl LiveEventOptions {
+    fn wants(&self, status: &LiveEventStatus) -> bool {
+        match status {
+            LiveEventStatus::Live => true,
+            LiveEventStatus::Planned => self.notify_planned,
+            LiveEventStatus::Ended => self.notify_ended,
+            _ => false,
+        }
+    }
+}
+
+/// Which senders to notify about. Muted senders are always dropped, and when
+/// an allow list is set only senders on it get through.
+#[derive(Debug, Clone, Default)]
+pub struct SenderFilter {
+    pub muted: HashSet<PublicKey>,
+    pub allowed: Option<HashSet<PublicKey>>,
+    /// The public entries of each watched pubkey's NIP-51 mute list, along
+    /// with when the list was published.
+    mute_lists: HashMap<PublicKey, (Timestamp, HashSet<PublicKey>)>,
+}
+
+impl SenderFilter {
+    /// Replaces the author's mute list if the event is newer than the one
+    /// already known.
+    fn update_mute_list(&mut self, event: &Event) {
+        if let Some((created_at, _)) = self.mute_lists.get(event.author_ref()) {
+            if *created_at >= event.created_at() {
+                return;
+            }
+        }
+
+        let muted: HashSet<PublicKey> = event.public_keys().copied().collect();
+        info!(
+            "Muting {} pubkeys from the mute list of {}",
+            muted.len(),
+            event.author()
+        );
+        self.mute_lists
+            .insert(event.author(), (event.created_at(), muted));
+    }
+
+    fn is_muted(&self, sender: &PublicKey) -> bool {
+        self.muted.contains(sender)
+            || self
+                .mute_lists
+                .values()
+                .any(|(_, muted)| muted.contains(sender))
+    }
+
+    /// Whether to notify about something from the sender. An unknown sender,
+    /// like an anonymous zap, is only dropped when an allow list is set.
+    fn allows(&self, sender: Option<&PublicKey>) -> bool {
+        let Some(sender) = sender else {
+            return self.allowed.is_none();
+        };
+        if self.is_muted(sender) {
+            return false;
+        }
+
+        match &self.allowed {
+            Some(allowed) => allowed.contains(sender),
+            None => true,
+        }
+    }
+}
+
+pub async fn send_notifications(
+    notifier: Notifier,
+    names: MetadataCache,
+    keys: Option<Keys>,
+    options: NotifierOptions,
+    mut live_events: LiveEventState,
+    private_dm_since: Timestamp,
+    mut channel: Receiver<ReceivedEvent>,
+) -> Result<()> {
+    info!("Starting notifier loop.");
+    let NotifierOptions {
+        zaps: zap_options,
+        digests: digest_options,
+        live_events: live_event_options,
+        mut senders,
+        enabled,
+    } = options;
+    let (zap_sender, receiver) = mpsc::channel(100);
+    let zap_aggregator = tokio::spawn(aggregate(receiver, zap_options.window, {
+        let notifier = notifier.clone();
+        let names = names.clone();
+        let options = zap_options.clone();
+        move |batch| notify_zaps(notifier.clone(), names.clone(), options.clone(), batch)
+    }));
+    let (summary_sender, receiver) = mpsc::channel(100);
+    let summarizer = tokio::spawn(summarize_zaps(
+        notifier.clone(),
+        names.clone(),
+        zap_options.clone(),
+        receiver,
+    ));
+    let (dm_sender, receiver) = mpsc::channel(100);
+    let dm_aggregator = tokio::spawn(aggregate(receiver, digest_options.dm_window, {
+        let notifier = notifier.clone();
+        move |batch| notify_dms(notifier.clone(), batch)
+    }));
+    let (comment_sender, receiver) = mpsc::channel(100);
+    let comment_aggregator = tokio::spawn(aggregate(receiver, digest_options.comment_window, {
+        let notifier = notifier.clone();
+        move |batch| notify_comments(notifier.clone(), batch)
+    }));
+    live_events.reminders.rearm(&notifier).await;
+
+    while let Some(ReceivedEvent {
+        event,
+        mention,
+        unfollow,
+        relay_url,
+        goal_total_msats,
+    }) = channel.recv().await
+    {
+        let span = info_span!("notify", id = %event.id(), kind = %event.kind());
+        async {
+            debug!("Received event to notify about: {}", event.as_json());
+            let kind = notification_kind(&event, mention, goal_total_msats.is_some());
+            if let Some(kind) = kind.filter(|kind| !enabled.contains(*kind)) {
+                debug!(
+                    "Dropping event {} for disabled {:?} notifications",
+                    event.id(),
+                    kind
+                );
+                return;
+            }
+            // gift wraps and zaps are sent on someone else's behalf, so their
+            // sender is checked once it's known. live events and articles come
+            // from npubs that were explicitly configured, and mute lists and
+            // zap goals are our own.
+            let checked_later = matches!(
+                event.kind(),
+                Kind::GiftWrap
+                    | Kind::ZapReceipt
+                    | Kind::LiveEvent
+                    | Kind::LongFormTextNote
+                    | Kind::MuteList
+            ) || event.kind() == Kind::from(ZAP_GOAL_KIND);
+            if !checked_later && !senders.allows(Some(event.author_ref())) {
+                debug!("Dropping event {} from filtered sender", event.id());
+                return;
+            }
+
+            match event.kind() {
+                Kind::MuteList => senders.update_mute_list(&event),
+                kind if kind == Kind::from(ZAP_GOAL_KIND) => {
+                    let (Some(total_msats), Some(target_msats)) =
+                        (goal_total_msats, goal_target_msats(&event))
+                    else {
+                        return;
+                    };
+                    let Some(nevent) = to_nevent(event.id, Some(event.author()), &relay_url) else {
+                        return;
+                    };
+                    if let Err(err) = notifier
+                        .send_goal_reached_notification(
+                            &nevent,
+                            event.content(),
+                            target_msats,
+                            total_msats,
+                        )
+                        .await
+                    {
+                        error!("Unable to send zap goal notification: {}", err);
+                    }
+                }
+                Kind::EncryptedDirectMessage | Kind::GiftWrap => {
+                    // a legacy DM can still be announced without its content, but
+                    // a gift wrap hides who it's from until it's unwrapped
+                    let legacy = event.kind() == Kind::EncryptedDirectMessage;
+                    let Some(keys) = keys.as_ref() else {
+                        if legacy {
+                            let _ = dm_sender.send(None).await;
+                        }
+                        return;
+                    };
+                    let dm = match decrypt_dm(keys, &event) {
+                        Ok(Some(dm)) => dm,
+                        Ok(None) => return,
+                        Err(err) => {
+                            // the error never includes the content, so it's safe to log
+                            error!(
+                                "Unable to decrypt DM {}, check that nsec belongs to the npub it was sent to: {}",
+                                event.id(),
+                                err
+                            );
+                            if legacy {
+                                let _ = dm_sender.send(None).await;
+                            }
+                            return;
+                        }
+                    };
+                    if !legacy {
+                        // gift wraps are deduplicated by the watcher, so this
+                        // only skips DMs from before the last checkpoint
+                        if dm.created_at < private_dm_since {
+                            debug!("Ignoring private DM sent before the last checkpoint");
+                            return;
+                        }
+                        if !senders.allows(Some(&dm.sender)) {
+                            debug!("Dropping DM from filtered sender {}", dm.sender);
+                            return;
+                        }
+                    }
+
+                    let preview = (
+                        names.display_name(dm.sender).await,
+                        sanitize(&dm.content, DM_PREVIEW_LENGTH),
+                    );
+                    let _ = dm_sender.send(Some(preview)).await;
+                }
+                Kind::ZapReceipt => match get_zap(&event) {
+                    Ok(zap) if zap_options.below_min(zap.amount_msats) => {
+                        debug!(
+                            "Ignoring zap of {} millisats below minimum",
+                            zap.amount_msats
+                        );
+                    }
+                    Ok(zap) if !senders.allows(zap.sender.as_ref()) => {
+                        debug!("Dropping zap from filtered sender {:?}", zap.sender);
+                    }
+                    Ok(zap) => {
+                        if zap_options.daily_summary.enabled {
+                            let _ = summary_sender.send(zap.clone()).await;
+                        }
+                        let _ = zap_sender.send(zap).await;
+                    }
+                    Err(err) => error!("Unable to get amount in zap receipt: {}", err),
+                },
+                Kind::TextNote if mention => {
+                    let Some(nevent) = to_nevent(event.id, Some(event.author()), &relay_url) else {
+                        return;
+                    };
+                    let author = names.display_name(event.author()).await;
+                    if let Err(err) = notifier.send_mention_notification(&nevent, &author).await {
+                        error!("Unable to send mention notification: {}", err);
+                    }
+                }
+                Kind::TextNote => {
+                    let Some(nevent) = to_nevent(event.id, Some(event.author()), &relay_url) else {
+                        return;
+                    };
+                    let comment = Comment {
+                        nevent,
+                        author: names.display_name(event.author()).await,
+                        preview: sanitize(event.content(), COMMENT_PREVIEW_LENGTH),
+                        icon: names.picture(event.author()).await,
+                    };
+                    let _ = comment_sender.send(comment).await;
+                }
+                Kind::Reaction => {
+                    let Some(nevent) = to_nevent(event.id, Some(event.author()), &relay_url) else {
+                        return;
+                    };
+                    let author = names.display_name(event.author()).await;
+                    if let Err(err) = notifier
+                        .send_reaction_notification(&nevent, &author, event.content())
+                        .await
+                    {
+                        error!("Unable to send reaction notification: {}", err);
+                    }
+                }
+                Kind::Repost | Kind::GenericRepost => {
+                    let Some(reposted_id) = event.event_ids().next() else {
+                        return;
+                    };
+                    // the reposted note is ours, so it's likely on the same relay
+                    let Some(nevent) = to_nevent(*reposted_id, None, &relay_url) else {
+                        return;
+                    };
+                    let author = names.display_name(event.author()).await;
+                    if let Err(err) = notifier.send_repost_notification(&nevent, &author).await {
+                        error!("Unable to send repost notification: {}", err);
+                    }
+                }
+                Kind::LongFormTextNote => {
+                    let Some(identifier) = event.identifier() else {
+                        return;
+                    };
+                    let naddr = match to_naddr(event.kind(), event.author(), identifier, &relay_url)
+                    {
+                        Ok(naddr) => naddr,
+                        Err(err) => {
+                            error!("Unable to encode article {}: {}", event.id(), err);
+                            return;
+                        }
+                    };
+                    let title = event
+                        .tags()
+                        .iter()
+                        .find_map(|tag| match tag.as_standardized() {
+                            Some(TagStandard::Title(title)) => Some(title.as_str()),
+                            _ => None,
+                        });
+                    let author = names.display_name(event.author()).await;
+                    if let Err(err) = notifier
+                        .send_article_notification(&naddr, title, &author)
+                        .await
+                    {
+                        error!("Unable to send article notification: {}", err);
+                    }
+                }
+                Kind::ContactList => {
+                    let name = names.display_name(event.author()).await;
+                    if let Err(err) = notifier
+                        .send_follow_notification(event.author(), &name, unfollow)
+                        .await
+                    {
+                        error!("Unable to send follow notification: {}", err);
+                    }
+                }
+                Kind::LiveEvent => {
+                    notify_and_remind_event(
+                        &notifier,
+                        &names,
+                        &live_event_options,
+                        &mut live_events,
+                        event,
+                        relay_url,
+                    )
+                    .await;
+                }
+                _ => {}
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    // reminders may be hours away, so they're dropped rather than waited on.
+    // they're still on disk to be re-armed on the next start
+    live_events.reminders.cancel_all();
+
+    // closing the channels lets the aggregators flush anything pending
+    drop(zap_sender);
+    drop(summary_sender);
+    drop(dm_sender);
+    drop(comment_sender);
+    for aggregator in [
+        zap_aggregator,
+        summarizer,
+        dm_aggregator,
+        comment_aggregator,
+    ] {
+        if let Err(err) = aggregator.await {
+            error!("Aggregator task failed: {}", err);
+        }
+    }
+
+    info!("Notifier task complete");
+    Ok(())
+}
+
+pub const DEFAULT_RELAYS_DOWN_GRACE_SECS: u64 = 5 * 60;
+
+/// Follows relay connection changes and warns when no relay has been
+/// connected for the grace period, then again once one reconnects, so a
+/// silent watcher doesn't go unnoticed.
+pub async fn watch_connectivity(
+    client: Client,
+    notifier: Notifier,
+    grace: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut notifications = client.notifications();
+    let mut connected = HashSet::new();
+    for (url, relay) in client.relays().await {
+        if relay.is_connected().await {
+            connected.insert(url);
+        }
+    }
+    // set while no relays are connected, to time the grace period from
+    let mut down_since = connected.is_empty().then(Instant::now);
+    let mut alerted = false;
+
+    loop {
+        let wait = match down_since {
+            Some(since) if !alerted => grace.saturating_sub(since.elapsed()),
+            // nothing to wait for until the next status change
+            _ => Duration::MAX,
+        };
+        let notification = select! {
+            _ = shutdown.cancelled() => return,
+            _ = sleep(wait) => {
+                alerted = true;
+                error!("No relays connected for {}", format_duration(grace));
+                if let Err(err) = notifier.send_disconnected_notification(grace).await {
+                    error!("Unable to send disconnected notification: {}", err);
+                }
+                continue;
+            }
+            notification = notifications.recv() => notification,
+        };
+
+        let (relay_url, status) = match notification {
+            Ok(RelayPoolNotification::RelayStatus { relay_url, status }) => (relay_url, status),
+            Ok(RelayPoolNotification::Shutdown) | Err(RecvError::Closed) => return,
+            _ => continue,
+        };
+        debug!("Relay {} is {}", relay_url, status);
+        if status == RelayStatus::Connected {
+            connected.insert(relay_url);
+        } else {
+            connected.remove(&relay_url);
+        }
+
+        match (connected.is_empty(), down_since) {
+            (true, None) => {
+                warn!("Lost connection to every relay");
+                down_since = Some(Instant::now());
+            }
+            (false, Some(_)) => {
+                info!("Connected to a relay again");
+                down_since = None;
+                if std::mem::take(&mut alerted) {
+                    if let Err(err) = notifier.send_reconnected_notification().await {
+                        error!("Unable to send reconnected notification: {}", err);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The type of notification an event would be sent as, if any.
+fn notification_kind(event: &Event, mention: bool, goal_reached: bool) -> Option<NotificationKind> {
+    let kind = match event.kind() {
+        Kind::EncryptedDirectMessage | Kind::GiftWrap => NotificationKind::Dm,
+        Kind::ZapReceipt => NotificationKind::Zap,
+        Kind::TextNote if mention => NotificationKind::Mention,
+        Kind::TextNote => NotificationKind::Comment,
+        Kind::Reaction => NotificationKind::Reaction,
+        Kind::Repost | Kind::GenericRepost => NotificationKind::Repost,
+        Kind::ContactList => NotificationKind::Follow,
+        Kind::LiveEvent => NotificationKind::Event,
+        Kind::LongFormTextNote => NotificationKind::Article,
+        _ if goal_reached => NotificationKind::Goal,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+/// Makes user-provided text safe to show in a notification. Control
+/// characters are dropped, newlines and runs of whitespace are collapsed into
+/// single spaces, and anything past `max_chars` is cut off with an ellipsis.
+fn sanitize(content: &str, max_chars: usize) -> String {
+    let cleaned: String = content
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+    let collapsed = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut chars = collapsed.chars();
+    let truncated: String = chars.by_ref().take(max_chars).collect();
+    if chars.next().is_some() {
+        format!("{}…", truncated)
+    } else {
+        truncated
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ZapAggregate {
+    total_msats: u64,
+    senders: HashMap<PublicKey, u64>,
+    comments: Vec<String>,
+    anonymous: bool,
+}
+
+impl ZapAggregate {
+    fn add(&mut self, zap: Zap) {
+        self.total_msats = self.total_msats.saturating_add(zap.amount_msats);
+        match zap.sender {
+            Some(sender) => {
+                let total = self.senders.entry(sender).or_default();
+                *total = total.saturating_add(zap.amount_msats);
+            }
+            None => self.anonymous = true,
+        }
+        if let Some(comment) = zap.comment {
+            self.comments.push(sanitize(&comment, ZAP_COMMENT_LENGTH));
+        }
+    }
+
+    fn top_senders(&self, n: usize) -> Vec<(PublicKey, u64)> {
+        let mut senders: Vec<_> = self.senders.iter().map(|(k, v)| (*k, *v)).collect();
+        senders.sort_by(|a, b| b.1.cmp(&a.1));
+        senders.truncate(n);
+        senders
+    }
+
+    /// Senders that will be named in the notification message.
+    fn named_senders(&self) -> Vec<PublicKey> {
+        if self.anonymous {
+            return Vec::new();
+        }
+
+        self.top_senders(TOP_ZAPPERS)
+            .into_iter()
+            .map(|(sender, _)| sender)
+            .collect()
+    }
+
+    async fn message(
+        &self,
+        names: &MetadataCache,
+        messages: &Messages,
+        markdown: bool,
+        fiat: Option<&Fiat>,
+    ) -> String {
+        let mut resolved = HashMap::new();
+        for sender in self.named_senders() {
+            resolved.insert(sender, names.display_name(sender).await);
+        }
+
+        self.format_message(&resolved, messages, markdown, fiat)
+    }
+
+    async fn summary_message(
+        &self,
+        names: &MetadataCache,
+        messages: &Messages,
+        markdown: bool,
+        fiat: Option<&Fiat>,
+    ) -> String {
+        let amount = bold(&format_amount(self.total_msats, messages, fiat), markdown);
+        if self.senders.is_empty() {
+            return fill(&messages.summary_anonymous, &[("amount", &amount)]);
+        }
+
+        let mut top = Vec::new();
+        for (sender, amount_msats) in self.top_senders(TOP_ZAPPERS) {
+            let name = bold(&names.display_name(sender).await, markdown);
+            top.push(format!(
+                "{} ({})",
+                name,
+                format_amount(amount_msats, messages, fiat)
+            ));
+        }
+        fill(
+            &messages.summary,
+            &[
+                ("amount", &amount),
+                ("count", &self.senders.len().to_string()),
+                ("top", &top.join(", ")),
+            ],
+        )
+    }
+
+    fn format_message(
+        &self,
+        names: &HashMap<PublicKey, String>,
+        messages: &Messages,
+        markdown: bool,
+        fiat: Option<&Fiat>,
+    ) -> String {
+        let mut message = self.format_amounts(names, messages, markdown, fiat);
+        // zap comments are short messages to us, so a few are shown even when
+        // zaps are rolled up
+        if !self.comments.is_empty() {
+            let comments: Vec<_> = self
+                .comments
+                .iter()
+                .take(ZAP_COMMENTS)
+                .map(|comment| escape(comment, markdown))
+                .collect();
+            // a single sender's comments read as them talking, while a roll up
+            // already ends with a list of its own
+            let single = !self.anonymous && self.senders.len() == 1;
+            message.push_str(if single { ": " } else { " — " });
+            message.push_str(&comments.join(" / "));
+            let more = self.comments.len() - comments.len();
+            if more > 0 {
+                message.push_str(&format!(" (+{})", more));
+            }
+        }
+        message
+    }
+
+    fn format_amounts(
+        &self,
+        names: &HashMap<PublicKey, String>,
+        messages: &Messages,
+        markdown: bool,
+        fiat: Option<&Fiat>,
+    ) -> String {
+        let sats = |msats: u64| format_amount(msats, messages, fiat);
+        let amount = bold(&sats(self.total_msats), markdown);
+        if self.anonymous || self.senders.is_empty() {
+            return fill(&messages.zap_anonymous, &[("amount", &amount)]);
+        }
+
+        let name = |sender: &PublicKey| {
+            let name = names
+                .get(sender)
+                .cloned()
+                .unwrap_or_else(|| short_npub(sender));
+            bold(&name, markdown)
+        };
+
+        if self.senders.len() == 1 {
+            let sender = self.senders.keys().next().unwrap();
+            return fill(
+                &messages.zap_single,
+                &[("sender", &name(sender)), ("amount", &amount)],
+            );
+        }
+
+        let top: Vec<_> = self
+            .top_senders(TOP_ZAPPERS)
+            .into_iter()
+            .map(|(sender, amount_msats)| format!("{} ({})", name(&sender), sats(amount_msats)))
+            .collect();
+        fill(
+            &messages.zap_many,
+            &[
+                ("amount", &amount),
+                ("count", &self.senders.len().to_string()),
+                ("top", &top.join(", ")),
+            ],
+        )
+    }
+}
+
+/// Formats a zap amount in sats, along with its fiat value when known.
+fn format_amount(msats: u64, messages: &Messages, fiat: Option<&Fiat>) -> String {
+    let sats = msats_to_sats(msats);
+    let mut amount = fill(&messages.sats, &[("sats", &sats.to_string())]);
+    if let Some(fiat) = fiat {
+        amount.push_str(&format!(" ({})", fiat.format(sats)));
+    }
+    amount
+}
+
+/// Bolds the text when markdown is enabled, escaping anything that would
+/// otherwise be interpreted as markdown.
+fn bold(text: &str, markdown: bool) -> String {
+    if !markdown {
+        return text.to_string();
+    }
+
+    format!("**{}**", escape(text, markdown))
+}
+
+/// Escapes anything in user-provided text that would be interpreted as
+/// markdown, when markdown is enabled.
+fn escape(text: &str, markdown: bool) -> String {
+    if !markdown {
+        return text.to_string();
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn msats_to_sats(msats: u64) -> u64 {
+    msats / 1_000
+}
+
+/// Collects items until no more arrive within the window, then flushes them as
+/// a single batch. A zero window flushes every item on its own. Pending items
+/// are flushed when the channel closes.
+async fn aggregate<T, F, Fut>(mut receiver: Receiver<T>, window: Duration, mut flush: F)
+where
+    F: FnMut(Vec<T>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut closed = false;
+    while !closed {
+        let Some(item) = receiver.recv().await else {
+            return;
+        };
+        let mut batch = vec![item];
+
+        if !window.is_zero() {
+            debug!(
+                "Initial item received. Aggregating for {}s",
+                window.as_secs()
+            );
+
+            loop {
+                select! {
+                    _ = sleep(window) => break,
+                    item = receiver.recv() => {
+                        match item {
+                            Some(item) => batch.push(item),
+                            None => {
+                                closed = true;
+                                break;
+                            }
+                        }
+                    },
+                }
+            }
+        }
+
+        flush(batch).await;
+    }
+}
+
+async fn notify_zaps(
+    notifier: Notifier,
+    names: MetadataCache,
+    options: ZapOptions,
+    batch: Vec<Zap>,
+) {
+    let mut zaps = ZapAggregate::default();
+    for zap in batch {
+        zaps.add(zap);
+    }
+
+    if options.below_min(zaps.total_msats) {
+        debug!(
+            "Aggregated zaps of {} millisats below minimum. Not notifying.",
+            zaps.total_msats
+        );
+        return;
+    }
+
+    info!(
+        "Sending aggregated zap notification for amount {} millisats",
+        zaps.total_msats
+    );
+    let fiat = match &options.price {
+        Some(price) => price.fiat().await,
+        None => None,
+    };
+    let message = zaps
+        .message(&names, &notifier.messages, notifier.markdown, fiat.as_ref())
+        .await;
+    let icon = match zaps.named_senders().first() {
+        Some(sender) => names.picture(*sender).await,
+        None => None,
+    };
+    if let Err(err) = notifier
+        .send_zap_notification(zaps.total_msats, message, icon)
+        .await
+    {
+        error!("Unable to send zap notification: {}", err);
+    }
+}
+
+/// Tallies zaps through the day and sends the daily summary at the configured
+/// time. Days without zaps are skipped.
+async fn summarize_zaps(
+    notifier: Notifier,
+    names: MetadataCache,
+    options: ZapOptions,
+    mut receiver: Receiver<Zap>,
+) {
+    let summary = options.daily_summary;
+    if !summary.enabled {
+        return;
+    }
+
+    let mut zaps = ZapAggregate::default();
+    loop {
+        select! {
+            zap = receiver.recv() => match zap {
+                Some(zap) => zaps.add(zap),
+                None => return,
+            },
+            _ = sleep(summary.until_next(Utc::now())) => {
+                let today = std::mem::take(&mut zaps);
+                if today.total_msats == 0 {
+                    debug!("No zaps today. Skipping daily summary.");
+                    continue;
+                }
+
+                let fiat = match &options.price {
+                    Some(price) => price.fiat().await,
+                    None => None,
+                };
+                let message = today
+                    .summary_message(&names, &notifier.messages, notifier.markdown, fiat.as_ref())
+                    .await;
+                if let Err(err) = notifier.send_summary_notification(message).await {
+                    error!("Unable to send daily summary: {}", err);
+                }
+            }
+        }
+    }
+}
+
+async fn notify_dms(notifier: Notifier, mut batch: Vec<Option<(String, String)>>) {
+    let result = if batch.len() == 1 {
+        notifier.send_dm_notification(batch.remove(0)).await
+    } else {
+        notifier.send_dm_digest_notification(batch.len()).await
+    };
+    if let Err(err) = result {
+        error!("Unable to send DM notification: {}", err);
+    }
+}
+
+/// A comment waiting to be notified about.
+#[derive(Debug)]
+struct Comment {
+    nevent: String,
+    author: String,
+    preview: String,
+    icon: Option<String>,
+}
+
+async fn notify_comments(notifier: Notifier, mut batch: Vec<Comment>) {
+    let result = if batch.len() == 1 {
+        let comment = batch.remove(0);
+        notifier
+            .send_comment_notification(
+                &comment.nevent,
+                &comment.author,
+                &comment.preview,
+                comment.icon,
+            )
+            .await
+    } else {
+        let mut authors: Vec<String> = Vec::new();
+        for comment in &batch {
+            if !authors.contains(&comment.author) {
+                authors.push(comment.author.clone());
+            }
+        }
+        notifier
+            .send_comment_digest_notification(batch.len(), &authors)
+            .await
+    };
+    if let Err(err) = result {
+        error!("Unable to send comment notification: {}", err);
+    }
 }
 
-impl NtfyApiClient {
-    pub fn new(api: reqwest::Client, topic: impl ToString) -> Self {
-        Self {
-            api,
-            endpoint: format!("{}/{}", API_ENDPOINT, topic.to_string()),
+async fn notify_and_remind_event(
+    notifier: &Notifier,
+    names: &MetadataCache,
+    options: &LiveEventOptions,
+    state: &mut LiveEventState,
+    event: Event,
+    relay_url: Option<Url>,
+) {
+    let event_id = event.id();
+    let live_event = match tags_to_live_event(event.tags().iter().map(Clone::clone).collect()) {
+        Ok(event) => event,
+        Err(err) => {
+            error!("Unable to create a LiveEvent from the event: {}", err);
+            return;
         }
-    }
+    };
 
-    pub async fn send_dm_notification(&self) -> Result<()> {
-        info!("Sending notification about DM");
-        self.api
-            .post(&self.endpoint)
-            .header(TITLE, DM_TITLE)
-            .header(PRIORITY, Priority::Default)
-            .header(TAGS, "book")
-            .body("You've received a new nostr DM.")
-            .send()
-            .await?;
+    let Some(status) = live_event.status.clone() else {
+        debug!("Live event {} has no status. Skipping.", event_id);
+        return;
+    };
+    // link to the coordinate so the notification keeps opening the event after
+    // it's republished
+    let naddr = match to_naddr(event.kind(), event.author(), &live_event.id, &relay_url) {
+        Ok(naddr) => naddr,
+        Err(err) => {
+            error!("Unable to encode live event {}: {}", event_id, err);
+            return;
+        }
+    };
+    let coordinate = (event.kind(), event.author(), live_event.id.clone());
 
-        Ok(())
+    // recordings are usually added after the event ends, without its status
+    // changing, so they're tracked separately
+    if let Some(recording) = live_event
+        .recording
+        .as_ref()
+        .filter(|_| options.notify_recordings)
+    {
+        if state.recordings.insert(coordinate.clone()).await {
+            if let Err(err) = notifier
+                .send_recording_notification(&naddr, &live_event, &recording.to_string())
+                .await
+            {
+                error!("Unable to send recording notification: {}", err);
+            }
+        }
     }
 
-    pub async fn send_zap_notification(&self, amount_ms: u64) -> Result<()> {
-        let amount = amount_ms / 1_000;
-        info!(
-            "Sending notification about zaps with amount {} sats",
-            amount
-        );
-        let message = format!("You've received {} sats in zaps on your post!", amount);
-
-        self.api
-            .post(&self.endpoint)
-            .header(TITLE, ZAPS_TITLE)
-            .header(PRIORITY, Priority::Default)
-            .header(TAGS, "moneybag")
-            .body(message)
-            .send()
-            .await?;
+    if !state
+        .statuses
+        .update(coordinate.clone(), event.created_at(), status.clone())
+        .await
+    {
+        trace!("Status of live event {} is unchanged. Skipping.", event_id);
+        return;
+    }
 
-        Ok(())
+    if options.wants(&status) {
+        // streaming services publish events for their users, who are tagged
+        // as the host
+        let host = live_event
+            .host
+            .as_ref()
+            .map_or(event.author(), |host| host.public_key);
+        let host = names.display_name(host).await;
+        if let Err(err) = notifier
+            .send_event_notification(&naddr, &live_event, &host)
+            .await
+        {
+            error!("Unable to send event notification: {}", err);
+        }
     }
 
-    pub async fn send_comment_notification(&self, event_id: EventId) -> Result<()> {
-        let event_id = event_id.to_bech32().unwrap();
-        info!("Sending notification about comment {}", event_id);
-        let message = "You've received a comment on your post!".to_string();
-        let uri = format!("nostr:{}", event_id);
+    // reminders only make sense for events that haven't started yet, so any
+    // left over from when it was planned are dropped
+    if status != LiveEventStatus::Planned {
+        state.reminders.cancel(notifier, &coordinate).await;
+        return;
+    }
 
-        self.api
-            .post(&self.endpoint)
-            .header(TITLE, COMMENT_TITLE)
-            .header(PRIORITY, Priority::Default)
-            .header(TAGS, "incoming_envelope")
-            .header(CLICK, uri)
-            .body(message)
-            .send()
-            .await?;
+    let Some(starts) = live_event.starts else {
+        return;
+    };
+    // reminders whose moment has already passed are skipped
+    let now = Timestamp::now().as_u64();
+    for offset in &options.reminder_offsets {
+        let remind_at = starts.as_u64().saturating_sub(offset.as_secs());
+        if remind_at <= now {
+            debug!(
+                "Too late to schedule the {} reminder for live event {}",
+                format_duration(*offset),
+                event_id
+            );
+            continue;
+        }
 
-        Ok(())
+        let reminder = Reminder {
+            coordinate: coordinate.clone(),
+            remind_at: Timestamp::from(remind_at),
+            relay_url: relay_url.clone(),
+            event: event.clone(),
+            scheduled_id: None,
+        };
+        let wait = Duration::from_secs(remind_at.saturating_sub(now));
+        match state.reminders.schedule(notifier, reminder).await {
+            Ok(true) => info!(
+                "Scheduled reminder about live event {} in {}",
+                naddr,
+                format_duration(wait)
+            ),
+            Ok(false) => warn!(
+                "Already holding {} reminders. Not scheduling another for live event {}",
+                state.reminders.max, naddr
+            ),
+            Err(err) => error!("Unable to schedule event reminder notification: {}", err),
+        }
     }
+}
 
-    pub async fn send_event_notification(
-        &self,
-        event_id: EventId,
-        event: &LiveEvent,
-    ) -> Result<()> {
-        let event_id = event_id.to_bech32().unwrap();
-        let title = event.title.clone().unwrap_or(format!("Event {}", event_id));
-
-        let starts_in = event.starts.unwrap_or_default() - Timestamp::now();
-        let starts_in = Duration::from_secs(starts_in.as_u64());
-
-        info!("Sending notification about live event {}", event_id);
-        let message = format!(r#"{} starts in {}"#, title, format_duration(starts_in));
-        let uri = format!("nostr:{}", event_id);
-
-        self.api
-            .post(&self.endpoint)
-            .header(TITLE, EVENT_TITLE)
-            .header(PRIORITY, Priority::Default)
-            .header(TAGS, "spiral_calendar")
-            .header(CLICK, uri)
-            .body(message)
-            .send()
-            .await?;
+pub const DEFAULT_MAX_PENDING_REMINDERS: usize = 100;
 
-        Ok(())
-    }
+/// Live event reminders that aren't due yet. Sinks that can schedule delivery
+/// are handed each one right away and it's tracked by the id the sink gave it.
+/// For other sinks it's held in a task until it's due, and the tasks live in a
+/// `JoinSet` so they're aborted if this is dropped. Either way reminders are
+/// kept by event so a status change can cancel them, and capped so a busy
+/// calendar can't pile them up. Each is also saved to disk with the event it's
+/// about, so reminders still in the future are re-armed after a restart.
+#[derive(Debug)]
+pub struct Reminders {
+    path: PathBuf,
+    max: usize,
+    tasks: JoinSet<()>,
+    /// Held reminders come with the handle of their task.
+    pending: HashMap<crate::nostr::Coordinate, Vec<(Reminder, Option<AbortHandle>)>>,
+    /// Loaded from disk and waiting to be re-armed once there's a notifier.
+    restored: Vec<Reminder>,
 }
 
-pub enum Priority {
-    Min = 1,
-    Low = 2,
-    Default = 3,
-    High = 4,
-    Max = 5,
+#[derive(Debug, Clone, PartialEq)]
+struct Reminder {
+    coordinate: crate::nostr::Coordinate,
+    remind_at: Timestamp,
+    relay_url: Option<Url>,
+    event: Event,
+    /// The id the sink knows the reminder by, once it's been handed over.
+    scheduled_id: Option<String>,
 }
 
-impl std::fmt::Display for Priority {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Max => write!(f, "max"),
-            Self::High => write!(f, "high"),
-            Self::Default => write!(f, "default"),
-            Self::Low => write!(f, "low"),
-            Self::Min => write!(f, "min"),
+impl Reminders {
+    pub async fn load(path: PathBuf, max: usize) -> Result<Self> {
+        let mut restored = Vec::new();
+        match read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some(reminder) = Self::parse_line(line) else {
+                        warn!("Skipping malformed reminder entry: {}", line);
+                        continue;
+                    };
+                    restored.push(reminder);
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
         }
+
+        Ok(Self {
+            path,
+            max,
+            tasks: JoinSet::new(),
+            pending: HashMap::new(),
+            restored,
+        })
     }
-}
 
-impl TryFrom<Priority> for HeaderValue {
-    type Error = http::Error;
+    fn parse_line(line: &str) -> Option<Reminder> {
+        // the event is last since its JSON may contain spaces
+        let mut parts = line.splitn(4, ' ');
+        let remind_at = parts.next()?.parse::<u64>().ok()?;
+        let relay_url = match parts.next()? {
+            "-" => None,
+            url => Some(Url::parse(url).ok()?),
+        };
+        let scheduled_id = match parts.next()? {
+            "-" => None,
+            id => Some(id.to_string()),
+        };
+        let event = Event::from_json(parts.next()?).ok()?;
+        let coordinate = (
+            event.kind(),
+            event.author(),
+            event.identifier()?.to_string(),
+        );
 
-    fn try_from(value: Priority) -> std::result::Result<Self, Self::Error> {
-        let n = (value as u8).to_string();
-        Ok(HeaderValue::from_str(&n)?)
+        Some(Reminder {
+            coordinate,
+            remind_at: Timestamp::from(remind_at),
+            relay_url,
+            event,
+            scheduled_id,
+        })
     }
-}
 
-pub async fn send_ntfy_messages(client: NtfyApiClient, mut channel: Receiver<Event>) -> Result<()> {
-    info!("Starting notifier loop.");
-    let (sender, receiver) = mpsc::channel(100);
-    tokio::spawn(aggregate_zaps(
-        receiver,
-        client.clone(),
-        Duration::from_secs(2 * 60),
-    ));
+    fn format_line(reminder: &Reminder) -> String {
+        format!(
+            "{} {} {} {}\n",
+            reminder.remind_at.as_u64(),
+            reminder
+                .relay_url
+                .as_ref()
+                .map_or("-".to_string(), ToString::to_string),
+            reminder.scheduled_id.as_deref().unwrap_or("-"),
+            reminder.event.as_json()
+        )
+    }
 
-    while let Some(event) = channel.recv().await {
-        debug!("Received event to notify about: {}", event.as_json());
-        match event.kind() {
-            Kind::EncryptedDirectMessage => {
-                let _ = client.send_dm_notification().await;
+    /// Re-arms the reminders loaded from disk that are still in the future.
+    /// Ones the sink already holds are only tracked again, so they can still
+    /// be cancelled.
+    async fn rearm(&mut self, notifier: &Notifier) {
+        let now = Timestamp::now();
+        let mut rearmed = 0;
+        for reminder in std::mem::take(&mut self.restored) {
+            if reminder.remind_at <= now {
+                debug!(
+                    "Reminder for live event {} was due while stopped. Dropping it.",
+                    reminder.coordinate.2
+                );
+                continue;
             }
-            Kind::ZapReceipt => match get_zap_request_amount(&event) {
-                Ok(amount) => {
-                    let _ = sender.send(amount).await;
-                }
-                Err(err) => error!("Unable to get amount in zap receipt: {}", err),
-            },
-            Kind::TextNote => {
-                let _ = client.send_comment_notification(event.id).await;
+            if reminder.scheduled_id.is_some() {
+                self.pending
+                    .entry(reminder.coordinate.clone())
+                    .or_default()
+                    .push((reminder, None));
+                rearmed += 1;
+                continue;
             }
-            Kind::LiveEvent => {
-                tokio::spawn(notify_and_remind_event(client.clone(), event));
+            match self.add(notifier, reminder).await {
+                Ok(true) => rearmed += 1,
+                Ok(false) => {}
+                Err(err) => error!("Unable to re-arm event reminder: {}", err),
             }
-            _ => {}
         }
+        if rearmed > 0 {
+            info!("Re-armed {} live event reminders", rearmed);
+        }
+        self.save().await;
     }
 
-    info!("Notifier task complete");
-    Ok(())
-}
+    /// Hands the reminder to the sink, or holds it until it's due, unless the
+    /// cap has been reached. Returns whether it was scheduled.
+    async fn schedule(&mut self, notifier: &Notifier, reminder: Reminder) -> Result<bool> {
+        let scheduled = self.add(notifier, reminder).await?;
+        if scheduled {
+            self.save().await;
+        }
+        Ok(scheduled)
+    }
 
-async fn aggregate_zaps(mut receiver: Receiver<u64>, client: NtfyApiClient, duration: Duration) {
-    loop {
-        let mut total = 0;
-        let Some(amount) = receiver.recv().await else {
-            return;
+    async fn add(&mut self, notifier: &Notifier, mut reminder: Reminder) -> Result<bool> {
+        self.prune();
+        if self.pending.values().map(Vec::len).sum::<usize>() >= self.max {
+            return Ok(false);
+        }
+
+        let handle = if notifier.schedules_delivery() {
+            let (naddr, live_event) = reminder_target(&reminder.event, &reminder.relay_url)?;
+            let id = notifier
+                .schedule_event_reminder(&naddr, &live_event, reminder.remind_at)
+                .await?;
+            reminder.scheduled_id = Some(id);
+            None
+        } else {
+            let wait = Duration::from_secs(
+                reminder
+                    .remind_at
+                    .as_u64()
+                    .saturating_sub(Timestamp::now().as_u64()),
+            );
+            Some(self.tasks.spawn({
+                let notifier = notifier.clone();
+                let event = reminder.event.clone();
+                let relay_url = reminder.relay_url.clone();
+                async move {
+                    sleep(wait).await;
+                    send_reminder(&notifier, &event, &relay_url).await;
+                }
+            }))
         };
-        total += amount;
-        debug!(
-            "Initial zap received. Aggregating zaps for {}s",
-            duration.as_secs()
-        );
+        self.pending
+            .entry(reminder.coordinate.clone())
+            .or_default()
+            .push((reminder, handle));
+        Ok(true)
+    }
 
-        loop {
-            select! {
-                _ = sleep(duration) => break,
-                a = receiver.recv() => {
-                    match a {
-                        Some(v) => total += v,
-                        None => return
+    /// Forgets reminders that have been delivered, joining finished tasks so
+    /// the set doesn't grow without bound.
+    fn prune(&mut self) {
+        while let Some(result) = self.tasks.try_join_next() {
+            match result {
+                Err(err) if err.is_panic() => error!("Live event reminder panicked: {}", err),
+                _ => {}
+            }
+        }
+        let now = Timestamp::now();
+        self.pending.retain(|_, reminders| {
+            reminders.retain(|(reminder, handle)| !is_delivered(reminder, handle, now));
+            !reminders.is_empty()
+        });
+    }
+
+    async fn cancel(&mut self, notifier: &Notifier, coordinate: &crate::nostr::Coordinate) {
+        let Some(reminders) = self.pending.remove(coordinate) else {
+            return;
+        };
+        let now = Timestamp::now();
+        let mut cancelled = 0;
+        for (reminder, handle) in reminders {
+            if is_delivered(&reminder, &handle, now) {
+                continue;
+            }
+            match (handle, &reminder.scheduled_id) {
+                (Some(handle), _) => handle.abort(),
+                (None, Some(id)) => {
+                    if let Err(err) = notifier.cancel_scheduled(id).await {
+                        warn!(
+                            "Unable to cancel the reminder for live event {}: {}",
+                            coordinate.2, err
+                        );
+                        continue;
                     }
-                },
+                }
+                (None, None) => continue,
             }
+            cancelled += 1;
         }
+        if cancelled > 0 {
+            debug!(
+                "Cancelled {} reminders for live event {}",
+                cancelled, coordinate.2
+            );
+        }
+        self.save().await;
+    }
 
-        info!(
-            "Sending aggregated zap notification for amount {} millisats",
-            total
-        );
-        let _ = client.send_zap_notification(total).await;
+    /// Stops every held reminder without forgetting them on disk. Ones the
+    /// sink holds are left to it.
+    fn cancel_all(&mut self) {
+        self.tasks.abort_all();
+        self.pending.clear();
+    }
+
+    /// Writes the reminders that haven't been delivered yet. Ones that were
+    /// delivered since the last save are left for the next, and skipped on
+    /// load since they're due.
+    async fn save(&self) {
+        let now = Timestamp::now();
+        let contents: String = self
+            .pending
+            .values()
+            .flatten()
+            .filter(|(reminder, handle)| !is_delivered(reminder, handle, now))
+            .map(|(reminder, _)| Self::format_line(reminder))
+            .collect();
+        if let Err(err) = write(&self.path, contents).await {
+            warn!("Unable to persist live event reminders: {}", err);
+        }
     }
 }
 
-async fn notify_and_remind_event(client: NtfyApiClient, event: Event) {
-    let event_id = event.id();
-    let live_event = match tags_to_live_event(event.tags().iter().map(Clone::clone).collect()) {
-        Ok(event) => event,
+/// Held reminders are delivered once their task finishes, and ones the sink
+/// holds once they're due.
+fn is_delivered(reminder: &Reminder, handle: &Option<AbortHandle>, now: Timestamp) -> bool {
+    match handle {
+        Some(handle) => handle.is_finished(),
+        None => reminder.remind_at <= now,
+    }
+}
+
+/// The naddr and parsed contents of the live event a reminder is about.
+fn reminder_target(event: &Event, relay_url: &Option<Url>) -> Result<(String, LiveEvent)> {
+    let live_event = tags_to_live_event(event.tags().iter().map(Clone::clone).collect())?;
+    let naddr = to_naddr(event.kind(), event.author(), &live_event.id, relay_url)?;
+    Ok((naddr, live_event))
+}
+
+/// Sends a reminder about the live event now that it's due.
+async fn send_reminder(notifier: &Notifier, event: &Event, relay_url: &Option<Url>) {
+    let (naddr, live_event) = match reminder_target(event, relay_url) {
+        Ok(target) => target,
         Err(err) => {
-            error!("Unable to create a LiveEvent from the event: {}", err);
+            error!(
+                "Unable to prepare reminder for live event {}: {}",
+                event.id(),
+                err
+            );
             return;
         }
     };
+    if let Err(err) = notifier.send_event_reminder(&naddr, &live_event).await {
+        error!("Unable to send event reminder notification: {}", err);
+    }
+}
+
+/// When a live event starts, in UTC since that's unambiguous for everyone
+/// watching.
+fn format_event_time(starts: Timestamp) -> String {
+    match i64::try_from(starts.as_u64())
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+    {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => starts.to_string(),
+    }
+}
 
-    let _ = client.send_event_notification(event_id, &live_event).await;
+/// The NIP-19 naddr of an addressable event. Unlike its event id, this keeps
+/// pointing at the latest version after the event is republished. The relay
+/// it was seen on is included as a hint for clients to find it.
+fn to_naddr(
+    kind: Kind,
+    author: PublicKey,
+    identifier: &str,
+    relay_url: &Option<Url>,
+) -> Result<String> {
+    let mut coordinate = Coordinate::new(kind, author).identifier(identifier);
+    coordinate.relays = relay_url.iter().map(ToString::to_string).collect();
+    Ok(coordinate.to_bech32()?)
+}
 
-    if let Some(starts) = live_event.starts {
-        // notify a half hour before the event starts
-        let diff = starts - Timestamp::now() - (60 * 30);
-        sleep(Duration::from_secs(diff.as_u64())).await;
-        let _ = client.send_event_notification(event_id, &live_event).await;
+/// The NIP-19 nevent of an event, including the relay it was seen on and its
+/// author as hints for clients to find it.
+fn to_nevent(
+    event_id: EventId,
+    author: Option<PublicKey>,
+    relay_url: &Option<Url>,
+) -> Option<String> {
+    let mut nevent = Nip19Event::new(event_id, relay_url.iter().map(ToString::to_string));
+    if let Some(author) = author {
+        nevent = nevent.author(author);
+    }
+    match nevent.to_bech32() {
+        Ok(nevent) => Some(nevent),
+        Err(err) => {
+            error!("Unable to encode event {}: {}", event_id, err);
+            None
+        }
     }
 }
 
@@ -240,8 +2690,10 @@ fn tags_to_live_event(tags: Vec<Tag>) -> Result<LiveEvent> {
         .iter()
         .find(|t| t.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::D)))
     {
-        Some(tag) if tag.content().is_none() => bail!("'d' tag missing content"),
-        Some(tag) => tag.content().map(String::from).unwrap(),
+        Some(tag) => match tag.content() {
+            Some(content) if !content.is_empty() => content.to_string(),
+            _ => bail!("'d' tag missing content"),
+        },
         None => bail!("'d' tag missing"),
     };
     let mut live_event = new_live_event(id);
@@ -309,3 +2761,508 @@ fn new_live_event(id: String) -> LiveEvent {
         participants: Vec::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zap_receipt(sender: &Keys, amount_msats: u64, comment: &str) -> Event {
+        let request = EventBuilder::new(
+            Kind::ZapRequest,
+            comment,
+            [Tag::parse(&["amount", amount_msats.to_string().as_str()]).unwrap()],
+        )
+        .to_event(sender)
+        .unwrap();
+
+        EventBuilder::new(
+            Kind::ZapReceipt,
+            "",
+            [Tag::parse(&["description", request.as_json().as_str()]).unwrap()],
+        )
+        .to_event(&Keys::generate())
+        .unwrap()
+    }
+
+    #[test]
+    fn zap_message_shows_sats_from_millisats() {
+        let sender = Keys::generate();
+        let receipt = zap_receipt(&sender, 21_000, "nice post!");
+
+        let zap = get_zap(&receipt).unwrap();
+        assert_eq!(zap.amount_msats, 21_000);
+
+        let mut zaps = ZapAggregate::default();
+        zaps.add(zap);
+        let names = HashMap::from([(sender.public_key(), "bob".to_string())]);
+
+        assert_eq!(
+            zaps.format_message(&names, &Messages::default(), false, None),
+            "bob zapped 21 sats: nice post!"
+        );
+        assert_eq!(
+            zaps.format_message(&names, &Messages::default(), true, None),
+            "**bob** zapped **21 sats**: nice post!"
+        );
+    }
+
+    #[test]
+    fn zap_comments_are_escaped_with_markdown() {
+        let sender = Keys::generate();
+        let zap = get_zap(&zap_receipt(&sender, 21_000, "see [this](http://x) *now*")).unwrap();
+
+        let mut zaps = ZapAggregate::default();
+        zaps.add(zap);
+        let names = HashMap::from([(sender.public_key(), "bob".to_string())]);
+
+        assert_eq!(
+            zaps.format_message(&names, &Messages::default(), true, None),
+            "**bob** zapped **21 sats**: see \\[this\\](http://x) \\*now\\*"
+        );
+        assert_eq!(
+            zaps.format_message(&names, &Messages::default(), false, None),
+            "bob zapped 21 sats: see [this](http://x) *now*"
+        );
+    }
+
+    #[test]
+    fn rolled_up_zaps_show_a_few_comments() {
+        let mut zaps = ZapAggregate::default();
+        for (i, comment) in ["gm", "", "great stream", "🔥", "more"].iter().enumerate() {
+            let sender = Keys::generate();
+            zaps.add(get_zap(&zap_receipt(&sender, (i as u64 + 1) * 1_000, comment)).unwrap());
+        }
+
+        let message = zaps.format_message(&HashMap::new(), &Messages::default(), false, None);
+        assert!(
+            message.ends_with(" — gm / great stream / 🔥 (+1)"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn zap_totals_handle_amounts_beyond_u32() {
+        let sender = Keys::generate();
+        // 5,000,000 sats is more millisats than fit in a u32
+        let receipt = zap_receipt(&sender, 5_000_000_000, "");
+
+        let mut zaps = ZapAggregate::default();
+        zaps.add(get_zap(&receipt).unwrap());
+        zaps.add(get_zap(&receipt).unwrap());
+        assert_eq!(zaps.total_msats, 10_000_000_000);
+        assert_eq!(msats_to_sats(zaps.total_msats), 10_000_000);
+
+        zaps.add(Zap {
+            sender: None,
+            amount_msats: u64::MAX,
+            comment: None,
+        });
+        assert_eq!(zaps.total_msats, u64::MAX);
+    }
+
+    #[test]
+    fn sender_filter_drops_muted_senders() {
+        let muted = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let senders = SenderFilter {
+            muted: HashSet::from([muted]),
+            allowed: None,
+            ..Default::default()
+        };
+
+        assert!(!senders.allows(Some(&muted)));
+        assert!(senders.allows(Some(&other)));
+        assert!(senders.allows(None));
+    }
+
+    #[test]
+    fn sender_filter_only_lets_allowed_senders_through() {
+        let allowed = Keys::generate().public_key();
+        let muted = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let senders = SenderFilter {
+            muted: HashSet::from([muted]),
+            allowed: Some(HashSet::from([allowed, muted])),
+            ..Default::default()
+        };
+
+        assert!(senders.allows(Some(&allowed)));
+        assert!(!senders.allows(Some(&other)));
+        // muting wins over allowing
+        assert!(!senders.allows(Some(&muted)));
+        assert!(!senders.allows(None));
+    }
+
+    #[test]
+    fn sender_filter_applies_the_newest_mute_list() {
+        let keys = Keys::generate();
+        let spammer = Keys::generate().public_key();
+        let reformed = Keys::generate().public_key();
+        let mute_list = |muted: PublicKey, created_at: u64| {
+            EventBuilder::new(Kind::MuteList, "", [Tag::public_key(muted)])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let mut senders = SenderFilter::default();
+
+        senders.update_mute_list(&mute_list(reformed, 100));
+        assert!(!senders.allows(Some(&reformed)));
+
+        senders.update_mute_list(&mute_list(spammer, 200));
+        assert!(!senders.allows(Some(&spammer)));
+        assert!(senders.allows(Some(&reformed)));
+
+        // an older list arriving late doesn't replace the newer one
+        senders.update_mute_list(&mute_list(reformed, 150));
+        assert!(senders.allows(Some(&reformed)));
+    }
+
+    fn quiet_hours(start: &str, end: &str) -> QuietHours {
+        QuietHours {
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            timezone: Tz::UTC,
+            break_through_sats: None,
+            types: EnabledNotifications::default(),
+        }
+    }
+
+    #[test]
+    fn zap_tiers_pick_their_own_priorities() {
+        let tiers = ZapTiers {
+            small_priority: Priority::Default,
+            ..Default::default()
+        };
+        assert!(matches!(
+            tiers.priority(&tiers.tier(10)),
+            Some(Priority::Default)
+        ));
+        assert!(tiers.priority(&tiers.tier(5_000)).is_none());
+        assert!(matches!(
+            tiers.priority(&tiers.tier(250_000)),
+            Some(Priority::Max)
+        ));
+    }
+
+    #[test]
+    fn zap_tiers_pick_their_own_tags() {
+        let tiers = ZapTiers {
+            tags: HashMap::from([
+                (ZapTier::Medium, "coin".to_string()),
+                (ZapTier::Large, "money_mouth_face".to_string()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(tiers.tags(&tiers.tier(10)), None);
+        assert_eq!(
+            tiers.tags(&tiers.tier(5_000)).map(String::as_str),
+            Some("coin")
+        );
+        assert_eq!(
+            tiers.tags(&tiers.tier(250_000)).map(String::as_str),
+            Some("money_mouth_face")
+        );
+    }
+
+    #[test]
+    fn quiet_hours_wrap_past_midnight() {
+        // 2024-01-01T00:00:00Z
+        let midnight = 1_704_067_200;
+        let at = |hour: u64| Timestamp::from(midnight + hour * 60 * 60);
+        let quiet = quiet_hours("22:00", "07:00");
+
+        assert!(quiet.contains(at(23)));
+        assert!(quiet.contains(at(3)));
+        assert!(!quiet.contains(at(7)));
+        assert!(!quiet.contains(at(12)));
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let midnight = 1_704_067_200;
+        let at = |hour: u64| Timestamp::from(midnight + hour * 60 * 60);
+        let quiet = quiet_hours("13:00", "15:00");
+
+        assert!(quiet.contains(at(14)));
+        assert!(!quiet.contains(at(15)));
+        assert!(!quiet.contains(at(3)));
+    }
+
+    #[test]
+    fn quiet_hours_apply_only_to_their_types() {
+        let midnight = Timestamp::from(1_704_067_200);
+        let quiet = QuietHours {
+            types: serde_json::from_str(r#"["zap"]"#).unwrap(),
+            ..quiet_hours("22:00", "07:00")
+        };
+        let notifier =
+            Notifier::new(Arc::new(CollectingSink::default())).with_quiet_hours(Some(quiet));
+
+        let zap = notifier.quiet_at(Some(NotificationKind::Zap), Priority::High, midnight);
+        assert!(matches!(zap, Priority::Min));
+        let dm = notifier.quiet_at(Some(NotificationKind::Dm), Priority::High, midnight);
+        assert!(matches!(dm, Priority::High));
+        let reconnected = notifier.quiet_at(None, Priority::Default, midnight);
+        assert!(matches!(reconnected, Priority::Min));
+    }
+
+    #[test]
+    fn daily_summary_is_due_at_the_next_configured_time() {
+        let summary = DailySummary {
+            enabled: true,
+            time: "21:00".parse().unwrap(),
+            timezone: "America/Chicago".parse().unwrap(),
+        };
+        let hours = |h: u64| Duration::from_secs(h * 60 * 60);
+
+        // 2024-01-01T12:00:00Z is 06:00 in Chicago
+        let morning = DateTime::from_timestamp(1_704_110_400, 0).unwrap();
+        assert_eq!(summary.until_next(morning), hours(15));
+        // 2024-01-02T03:00:00Z is 21:00 in Chicago, so the next one is tomorrow
+        let at_time = DateTime::from_timestamp(1_704_164_400, 0).unwrap();
+        assert_eq!(summary.until_next(at_time), hours(24));
+    }
+
+    fn tags(tags: &[&[&str]]) -> Vec<Tag> {
+        tags.iter().map(|t| Tag::parse(t).unwrap()).collect()
+    }
+
+    #[test]
+    fn live_event_from_complete_tags() {
+        let host = Keys::generate().public_key().to_hex();
+        let speaker = Keys::generate().public_key().to_hex();
+        let participant = Keys::generate().public_key().to_hex();
+        let event = tags_to_live_event(tags(&[
+            &["d", "stream"],
+            &["title", "Rabbit Hole Recap"],
+            &["summary", "Weekly bitcoin news"],
+            &["status", "live"],
+            &["starts", "1704067200"],
+            &["ends", "1704070800"],
+            &["t", "bitcoin"],
+            &["p", &host, "", "Host"],
+            &["p", &speaker, "", "Speaker"],
+            &["p", &participant, "", "Participant"],
+        ]))
+        .unwrap();
+
+        assert_eq!(event.id, "stream");
+        assert_eq!(event.title.as_deref(), Some("Rabbit Hole Recap"));
+        assert_eq!(event.summary.as_deref(), Some("Weekly bitcoin news"));
+        assert_eq!(event.status, Some(LiveEventStatus::Live));
+        assert_eq!(event.starts, Some(Timestamp::from(1_704_067_200)));
+        assert_eq!(event.ends, Some(Timestamp::from(1_704_070_800)));
+        assert_eq!(event.hashtags, vec!["bitcoin".to_string()]);
+        assert_eq!(event.host.unwrap().public_key.to_hex(), host);
+        assert_eq!(event.speakers.len(), 1);
+        assert_eq!(event.speakers[0].0.to_hex(), speaker);
+        assert_eq!(event.participants.len(), 1);
+        assert_eq!(event.participants[0].0.to_hex(), participant);
+    }
+
+    #[test]
+    fn live_event_requires_d_tag() {
+        assert!(tags_to_live_event(tags(&[&["title", "No Identifier"]])).is_err());
+    }
+
+    #[test]
+    fn live_event_requires_d_tag_content() {
+        assert!(tags_to_live_event(tags(&[&["d"], &["title", "Empty Identifier"]])).is_err());
+        assert!(tags_to_live_event(tags(&[&["d", ""], &["title", "Empty Identifier"]])).is_err());
+    }
+
+    #[test]
+    fn live_event_ignores_unknown_tags() {
+        let event = tags_to_live_event(tags(&[
+            &["d", "stream"],
+            &["title", "Stream"],
+            &["unknown", "value"],
+            &["x", "y", "z"],
+        ]))
+        .unwrap();
+
+        assert_eq!(event.id, "stream");
+        assert_eq!(event.title.as_deref(), Some("Stream"));
+        assert!(event.hashtags.is_empty());
+        assert!(event.host.is_none());
+    }
+
+    /// Keeps what it's given instead of delivering it.
+    #[derive(Debug, Default)]
+    struct CollectingSink(std::sync::Mutex<Vec<Notification>>);
+
+    #[async_trait]
+    impl NotificationSink for CollectingSink {
+        async fn deliver(&self, notification: &Notification) -> Result<()> {
+            self.0.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn going_live_is_announced_apart_from_scheduling() {
+        let sink = Arc::new(CollectingSink::default());
+        let notifier = Notifier::new(sink.clone());
+        let mut event = tags_to_live_event(tags(&[
+            &["d", "stream"],
+            &["title", "Rabbit Hole Recap"],
+            &["status", "planned"],
+            &["starts", "1704067200"],
+        ]))
+        .unwrap();
+
+        notifier
+            .send_event_notification("naddr1", &event, "rob")
+            .await
+            .unwrap();
+        event.status = Some(LiveEventStatus::Live);
+        notifier
+            .send_event_notification("naddr1", &event, "rob")
+            .await
+            .unwrap();
+
+        let sent = sink.0.lock().unwrap();
+        assert_eq!(
+            sent[0].message,
+            "rob scheduled a live event for 2024-01-01 00:00 UTC: Rabbit Hole Recap"
+        );
+        assert!(matches!(sent[0].priority, Priority::Default));
+        assert_eq!(sent[1].message, "rob is LIVE now: Rabbit Hole Recap");
+        assert!(matches!(sent[1].priority, Priority::High));
+    }
+
+    fn reminder(remind_at: Timestamp) -> Reminder {
+        let event = EventBuilder::new(
+            Kind::LiveEvent,
+            "",
+            tags(&[&["d", "weekly stream"], &["status", "planned"]]),
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+        Reminder {
+            coordinate: (Kind::LiveEvent, event.author(), "weekly stream".to_string()),
+            remind_at,
+            relay_url: Some(Url::parse("wss://relay.example.com").unwrap()),
+            event,
+            scheduled_id: None,
+        }
+    }
+
+    #[test]
+    fn reminders_round_trip_through_their_lines() {
+        let reminder = reminder(Timestamp::from(1_704_067_200));
+        let line = Reminders::format_line(&reminder);
+        assert_eq!(
+            Reminders::parse_line(line.trim_end()),
+            Some(reminder.clone())
+        );
+
+        let scheduled = Reminder {
+            relay_url: None,
+            scheduled_id: Some("sPs71M8A2T".to_string()),
+            ..reminder
+        };
+        let line = Reminders::format_line(&scheduled);
+        assert!(line.contains(" - sPs71M8A2T "));
+        assert_eq!(Reminders::parse_line(line.trim_end()), Some(scheduled));
+        assert_eq!(Reminders::parse_line("1704067200 - - {not json"), None);
+    }
+
+    /// Hands out an id for each scheduled notification and keeps the ones
+    /// cancelled.
+    #[derive(Debug, Default)]
+    struct SchedulingSink {
+        scheduled: std::sync::Mutex<Vec<Notification>>,
+        cancelled: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl NotificationSink for SchedulingSink {
+        async fn deliver(&self, _notification: &Notification) -> Result<()> {
+            Ok(())
+        }
+
+        fn schedules(&self) -> bool {
+            true
+        }
+
+        async fn schedule(&self, notification: &Notification) -> Result<String> {
+            let mut scheduled = self.scheduled.lock().unwrap();
+            scheduled.push(notification.clone());
+            Ok(format!("message{}", scheduled.len()))
+        }
+
+        async fn cancel(&self, id: &str) -> Result<()> {
+            self.cancelled.lock().unwrap().push(id.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn scheduled_reminders_are_capped_and_can_be_cancelled() {
+        let path =
+            std::env::temp_dir().join(format!("bullhorn-reminders-{}", uuid::Uuid::new_v4()));
+        let sink = Arc::new(SchedulingSink::default());
+        let notifier = Notifier::new(sink.clone());
+        let reminder = reminder(Timestamp::from(Timestamp::now().as_u64() + 60 * 60));
+
+        let mut reminders = Reminders::load(path.clone(), 1).await.unwrap();
+        assert!(reminders
+            .schedule(&notifier, reminder.clone())
+            .await
+            .unwrap());
+        assert!(!reminders
+            .schedule(&notifier, reminder.clone())
+            .await
+            .unwrap());
+        assert_eq!(sink.scheduled.lock().unwrap().len(), 1);
+        assert_eq!(
+            sink.scheduled.lock().unwrap()[0].at,
+            Some(reminder.remind_at)
+        );
+
+        // after a restart it's only tracked again, and can still be cancelled
+        let mut reminders = Reminders::load(path.clone(), 1).await.unwrap();
+        reminders.rearm(&notifier).await;
+        assert_eq!(sink.scheduled.lock().unwrap().len(), 1);
+        reminders.cancel(&notifier, &reminder.coordinate).await;
+        assert_eq!(
+            *sink.cancelled.lock().unwrap(),
+            vec!["message1".to_string()]
+        );
+        assert!(reminders.schedule(&notifier, reminder).await.unwrap());
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[test]
+    fn sanitize_strips_control_characters_and_collapses_whitespace() {
+        assert_eq!(
+            sanitize("gm\r\n\n  nostr\u{7}\tfriends\u{1b}[31m", 100),
+            "gm nostr friends[31m"
+        );
+    }
+
+    #[test]
+    fn sanitize_truncates_long_input() {
+        let long = "a".repeat(8 * 1024);
+
+        let sanitized = sanitize(&long, 140);
+
+        assert_eq!(sanitized.chars().count(), 141);
+        assert!(sanitized.ends_with('…'));
+        assert_eq!(sanitize("short", 140), "short");
+    }
+
+    #[test]
+    fn sanitize_truncates_emoji_on_char_boundaries() {
+        let emoji = "🤙⚡️🫂".repeat(2 * 1024);
+
+        let sanitized = sanitize(&emoji, 100);
+
+        assert_eq!(sanitized.chars().count(), 101);
+        assert!(emoji.starts_with(sanitized.trim_end_matches('…')));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+pub const DEFAULT_PRICE_API: &str = "https://mempool.space/api/v1/prices";
+
+/// How long a fetched price is reused before asking the price API again.
+const PRICE_TTL: Duration = Duration::from_secs(10 * 60);
+// a slow price API shouldn't hold up zap notifications
+const PRICE_TIMEOUT: Duration = Duration::from_secs(5);
+const SATS_PER_BTC: f64 = 100_000_000.0;
+
+/// Fetches the BTC price in a fiat currency, caching it for a short while so
+/// every zap doesn't hit the price API.
+#[derive(Debug, Clone)]
+pub struct PriceCache {
+    api: reqwest::Client,
+    url: String,
+    currency: String,
+    cached: Arc<RwLock<Option<(Instant, f64)>>>,
+}
+
+impl PriceCache {
+    /// The price API is expected to respond with a JSON object keyed by
+    /// currency code, like mempool.space's `{"USD": 65000, "EUR": 60000}`.
+    pub fn new(api: reqwest::Client, url: impl ToString, currency: &str) -> Self {
+        Self {
+            api,
+            url: url.to_string(),
+            currency: currency.to_uppercase(),
+            cached: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// The current price, or `None` if it can't be fetched. Failures are
+    /// logged so the notification can go out without the fiat value.
+    pub async fn fiat(&self) -> Option<Fiat> {
+        if let Some((fetched_at, price)) = *self.cached.read().unwrap() {
+            if fetched_at.elapsed() < PRICE_TTL {
+                return Some(self.with_price(price));
+            }
+        }
+
+        match self.fetch().await {
+            Ok(price) => {
+                debug!("BTC price is {} {}", price, self.currency);
+                *self.cached.write().unwrap() = Some((Instant::now(), price));
+                Some(self.with_price(price))
+            }
+            Err(err) => {
+                warn!("Unable to fetch BTC price: {:#}", err);
+                None
+            }
+        }
+    }
+
+    async fn fetch(&self) -> Result<f64> {
+        let response = self
+            .api
+            .get(&self.url)
+            .timeout(PRICE_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        let prices: Value = response.json().await?;
+        parse_price(&prices, &self.currency)
+    }
+
+    fn with_price(&self, price: f64) -> Fiat {
+        Fiat {
+            currency: self.currency.clone(),
+            price,
+        }
+    }
+}
+
+fn parse_price(prices: &Value, currency: &str) -> Result<f64> {
+    prices
+        .get(currency)
+        .and_then(Value::as_f64)
+        .with_context(|| format!("No {} price in response", currency))
+}
+
+/// A BTC price to convert sat amounts with.
+#[derive(Debug, Clone)]
+pub struct Fiat {
+    currency: String,
+    price: f64,
+}
+
+impl Fiat {
+    /// The approximate value of the sats, like `~$0.32` or `~0.30 CHF`.
+    pub fn format(&self, sats: u64) -> String {
+        let value = sats as f64 / SATS_PER_BTC * self.price;
+        match self.currency.as_str() {
+            "USD" => format!("~${:.2}", value),
+            "EUR" => format!("~€{:.2}", value),
+            "GBP" => format!("~£{:.2}", value),
+            currency => format!("~{:.2} {}", value, currency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn fiat_formats_with_symbol_or_code() {
+        let usd = Fiat {
+            currency: "USD".to_string(),
+            price: 64_000.0,
+        };
+        assert_eq!(usd.format(500), "~$0.32");
+
+        let chf = Fiat {
+            currency: "CHF".to_string(),
+            price: 60_000.0,
+        };
+        assert_eq!(chf.format(10_000), "~6.00 CHF");
+    }
+
+    #[test]
+    fn price_is_read_by_currency_code() {
+        let prices = json!({ "time": 1700000000, "USD": 64000, "EUR": 59000.5 });
+        assert_eq!(parse_price(&prices, "EUR").unwrap(), 59000.5);
+        assert!(parse_price(&prices, "JPY").is_err());
+    }
+}
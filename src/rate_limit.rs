@@ -0,0 +1,357 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::sleep;
+use tracing::{debug, error, warn};
+
+use crate::metrics::METRICS;
+use crate::sink::{Notification, NotificationSink};
+
+pub const DEFAULT_NOTIFICATION_RATE_PER_SEC: f64 = 2.0;
+pub const DEFAULT_NOTIFICATION_BURST: u32 = 10;
+/// The most notifications to hold while waiting on the limiter. Past this the
+/// oldest are dropped, since a backlog this long is hours stale anyway.
+const MAX_QUEUED: usize = 500;
+/// How many merged messages a digest lists before summarizing the rest.
+const DIGEST_LINES: usize = 5;
+
+/// Limits how fast notifications are handed to the sink, so a storm of events
+/// doesn't trip the service's rate limits. Notifications past the limit are
+/// queued, and queued ones of the same type are merged into a digest when
+/// they're sent.
+#[derive(Debug)]
+pub struct RateLimitedSink {
+    inner: Arc<dyn NotificationSink>,
+    bucket: Arc<Mutex<TokenBucket>>,
+    queued: Arc<AtomicUsize>,
+    sender: UnboundedSender<Notification>,
+}
+
+impl RateLimitedSink {
+    /// Wraps the sink, returning it along with the task that delivers queued
+    /// notifications. The task finishes once the sink is dropped and the
+    /// queue is empty.
+    pub fn new(
+        inner: Arc<dyn NotificationSink>,
+        rate_per_sec: f64,
+        burst: u32,
+    ) -> (Self, impl Future<Output = ()>) {
+        let bucket = Arc::new(Mutex::new(TokenBucket::new(
+            rate_per_sec,
+            burst,
+            Instant::now(),
+        )));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let worker = deliver_queued(inner.clone(), bucket.clone(), queued.clone(), receiver);
+
+        let sink = Self {
+            inner,
+            bucket,
+            queued,
+            sender,
+        };
+        (sink, worker)
+    }
+}
+
+#[async_trait]
+impl NotificationSink for RateLimitedSink {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        // notifications skip the queue only when nothing is waiting in it, so
+        // they're still sent in order
+        if self.queued.load(Ordering::Acquire) == 0
+            && self.bucket.lock().unwrap().try_take(Instant::now())
+        {
+            return self.inner.deliver(notification).await;
+        }
+
+        debug!(
+            "Rate limited. Queueing {} notification: {}",
+            notification.kind, notification.title
+        );
+        self.queued.fetch_add(1, Ordering::AcqRel);
+        METRICS.notification_queued();
+        if self.sender.send(notification.clone()).is_err() {
+            self.queued.fetch_sub(1, Ordering::AcqRel);
+            METRICS.notification_dropped();
+            warn!("Notification queue is closed. Dropping notification.");
+        }
+
+        Ok(())
+    }
+
+    fn schedules(&self) -> bool {
+        self.inner.schedules()
+    }
+
+    // scheduling hands back an id that's needed right away, so it can't wait
+    // in the queue. There are only ever a few reminders to schedule anyway
+    async fn schedule(&self, notification: &Notification) -> Result<String> {
+        self.inner.schedule(notification).await
+    }
+
+    async fn cancel(&self, id: &str) -> Result<()> {
+        self.inner.cancel(id).await
+    }
+}
+
+async fn deliver_queued(
+    inner: Arc<dyn NotificationSink>,
+    bucket: Arc<Mutex<TokenBucket>>,
+    queued: Arc<AtomicUsize>,
+    mut receiver: UnboundedReceiver<Notification>,
+) {
+    let mut pending = VecDeque::new();
+    loop {
+        if pending.is_empty() {
+            match receiver.recv().await {
+                Some(notification) => pending.push_back(notification),
+                None => break,
+            }
+        }
+        while let Ok(notification) = receiver.try_recv() {
+            pending.push_back(notification);
+        }
+        if pending.len() > MAX_QUEUED {
+            let dropped = pending.len() - MAX_QUEUED;
+            pending.drain(..dropped);
+            queued.fetch_sub(dropped, Ordering::AcqRel);
+            for _ in 0..dropped {
+                METRICS.notification_dropped();
+            }
+            warn!(
+                "Notification queue is full. Dropped the {} oldest notifications.",
+                dropped
+            );
+        }
+
+        let wait = {
+            let mut bucket = bucket.lock().unwrap();
+            let now = Instant::now();
+            (!bucket.try_take(now)).then(|| bucket.time_until_token(now))
+        };
+        if let Some(wait) = wait {
+            // more may arrive while waiting, to be merged with these
+            sleep(wait).await;
+            continue;
+        }
+
+        let batch = take_same_kind(&mut pending);
+        queued.fetch_sub(batch.len(), Ordering::AcqRel);
+        for _ in 1..batch.len() {
+            METRICS.notification_coalesced();
+        }
+        let notification = coalesce(batch);
+        if let Err(err) = inner.deliver(&notification).await {
+            METRICS.delivery_error();
+            error!(
+                "Unable to send queued {} notification: {}",
+                notification.kind, err
+            );
+        }
+    }
+    debug!("Notification queue closed");
+}
+
+/// Removes the oldest notification along with every other one of its type.
+/// Scheduled notifications are due at their own time, so they're never
+/// merged.
+fn take_same_kind(pending: &mut VecDeque<Notification>) -> Vec<Notification> {
+    let Some(front) = pending.front() else {
+        return Vec::new();
+    };
+    if front.at.is_some() {
+        return pending.pop_front().into_iter().collect();
+    }
+    let kind = front.kind;
+    let (batch, rest) = pending
+        .drain(..)
+        .partition(|notification| notification.kind == kind && notification.at.is_none());
+    *pending = rest;
+    batch
+}
+
+/// Merges notifications of the same type into one, listing the first few
+/// messages. A single notification is passed through untouched.
+fn coalesce(mut batch: Vec<Notification>) -> Notification {
+    let mut digest = batch.remove(0);
+    if batch.is_empty() {
+        return digest;
+    }
+
+    let count = batch.len() + 1;
+    let mut lines = vec![digest.message.clone()];
+    lines.extend(
+        batch
+            .iter()
+            .take(DIGEST_LINES - 1)
+            .map(|notification| notification.message.clone()),
+    );
+    if count > DIGEST_LINES {
+        lines.push(format!("…and {} more", count - DIGEST_LINES));
+    }
+
+    digest.title = format!("{} ({})", digest.title, count);
+    digest.message = lines.join("\n");
+    digest.priority = batch.iter().map(|notification| notification.priority).fold(
+        digest.priority,
+        |highest, priority| {
+            if priority as u8 > highest as u8 {
+                priority
+            } else {
+                highest
+            }
+        },
+    );
+    // the merged notifications point at different events, so there's nothing
+    // sensible for a tap or a button to open
+    digest.click = None;
+    digest.actions = Vec::new();
+    digest.icon = None;
+
+    digest
+}
+
+/// Refills at a steady rate up to a burst, with each notification taking one
+/// token.
+#[derive(Debug)]
+struct TokenBucket {
+    rate_per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_sec: f64, burst: u32, now: Instant) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            rate_per_sec,
+            burst,
+            tokens: burst,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate_per_sec).min(self.burst);
+        self.refilled_at = now;
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn time_until_token(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.rate_per_sec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostr_sdk::Timestamp;
+
+    use super::*;
+    use crate::ntfy::Priority;
+
+    fn notification(kind: &'static str, message: &str, priority: Priority) -> Notification {
+        Notification {
+            kind,
+            title: kind.to_string(),
+            message: message.to_string(),
+            priority,
+            tags: String::new(),
+            markdown: false,
+            click: Some(format!("nostr:{}", message)),
+            actions: Vec::new(),
+            icon: None,
+            at: None,
+        }
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 3, start);
+
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert_eq!(bucket.time_until_token(start), Duration::from_millis(500));
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn queued_notifications_of_a_type_are_taken_together() {
+        let mut pending = VecDeque::from([
+            notification("reaction", "a", Priority::Low),
+            notification("dm", "b", Priority::Default),
+            notification("reaction", "c", Priority::Low),
+        ]);
+
+        let batch = take_same_kind(&mut pending);
+        assert_eq!(batch.len(), 2);
+        assert!(batch.iter().all(|n| n.kind == "reaction"));
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].kind, "dm");
+
+        let scheduled = Notification {
+            at: Some(Timestamp::from(1_704_067_200)),
+            ..notification("event", "reminder", Priority::Default)
+        };
+        let mut pending = VecDeque::from([
+            scheduled,
+            notification("event", "live", Priority::High),
+            notification("event", "ended", Priority::Default),
+        ]);
+        assert_eq!(take_same_kind(&mut pending).len(), 1);
+        assert_eq!(take_same_kind(&mut pending).len(), 2);
+    }
+
+    #[test]
+    fn batches_are_coalesced_into_a_digest() {
+        let single = coalesce(vec![notification("zap", "a", Priority::Low)]);
+        assert_eq!(single.title, "zap");
+        assert_eq!(single.click.as_deref(), Some("nostr:a"));
+
+        let batch = ["a", "b", "c", "d", "e", "f", "g"]
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let priority = if i == 3 {
+                    Priority::High
+                } else {
+                    Priority::Low
+                };
+                notification("zap", message, priority)
+            })
+            .collect();
+        let digest = coalesce(batch);
+
+        assert_eq!(digest.title, "zap (7)");
+        assert_eq!(digest.message, "a\nb\nc\nd\ne\n…and 2 more");
+        assert!(matches!(digest.priority, Priority::High));
+        assert_eq!(digest.click, None);
+    }
+}
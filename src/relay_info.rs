@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::future::join_all;
+use nostr_sdk::nips::nip11::RelayInformationDocument;
+use nostr_sdk::{Client, Filter, Kind, Url};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+/// How long to wait on a relay for its NIP-11 document.
+const DOCUMENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Gift wraps, which NIP-17 DMs are sent in.
+const GIFT_WRAP_NIP: u16 = 59;
+
+/// What each relay said it supports in its NIP-11 document. Relays without a
+/// document are assumed to support everything.
+#[derive(Debug, Clone, Default)]
+pub struct RelayInfo(HashMap<Url, Limits>);
+
+#[derive(Debug, Clone, Default)]
+struct Limits {
+    supported_nips: Option<Vec<u16>>,
+    max_filters: Option<usize>,
+}
+
+impl RelayInfo {
+    /// Fetches the NIP-11 document of every relay the client has, logging what
+    /// each supports and warning about relays that won't serve us without
+    /// payment or authentication.
+    pub async fn fetch(client: &Client, proxy: Option<SocketAddr>, auth: bool) -> Self {
+        let urls: Vec<Url> = client.relays().await.into_keys().collect();
+        let documents = join_all(urls.iter().map(|url| async move {
+            match timeout(
+                DOCUMENT_TIMEOUT,
+                RelayInformationDocument::get(url.clone(), proxy),
+            )
+            .await
+            {
+                Ok(Ok(document)) => Some(document),
+                Ok(Err(err)) => {
+                    debug!("Unable to fetch the NIP-11 document of {}: {}", url, err);
+                    None
+                }
+                Err(_) => {
+                    debug!("Timed out fetching the NIP-11 document of {}", url);
+                    None
+                }
+            }
+        }))
+        .await;
+
+        let mut info = HashMap::new();
+        for (url, document) in urls.into_iter().zip(documents) {
+            let Some(document) = document else {
+                continue;
+            };
+            info.insert(url.clone(), Limits::from_document(&url, &document, auth));
+        }
+
+        Self(info)
+    }
+
+    /// Subscribes to the filters on every relay, leaving out the ones a relay
+    /// said it can't serve and splitting them up for relays that limit how
+    /// many filters a subscription can have.
+    pub async fn subscribe(&self, client: &Client, filters: &[Filter]) -> Result<()> {
+        for url in client.relays().await.into_keys() {
+            for filters in self.filters_for(&url, filters) {
+                client.subscribe_to([url.clone()], filters, None).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn filters_for(&self, url: &Url, filters: &[Filter]) -> Vec<Vec<Filter>> {
+        let Some(limits) = self.0.get(url) else {
+            return vec![filters.to_vec()];
+        };
+
+        let filters: Vec<Filter> = filters
+            .iter()
+            .filter(|filter| {
+                let served = !is_gift_wrap_filter(filter) || limits.supports(GIFT_WRAP_NIP);
+                if !served {
+                    warn!(
+                        "Relay {} doesn't support gift wraps. Not subscribing to DMs on it.",
+                        url
+                    );
+                }
+                served
+            })
+            .cloned()
+            .collect();
+        if filters.is_empty() {
+            return Vec::new();
+        }
+
+        match limits.max_filters {
+            Some(max) if max > 0 => filters.chunks(max).map(<[Filter]>::to_vec).collect(),
+            _ => vec![filters],
+        }
+    }
+}
+
+impl Limits {
+    fn from_document(url: &Url, document: &RelayInformationDocument, auth: bool) -> Self {
+        let limitation = document.limitation.as_ref();
+        info!(
+            "Relay {} ({}) supports NIPs {:?}",
+            url,
+            document.name.as_deref().unwrap_or("unnamed"),
+            document.supported_nips.as_deref().unwrap_or_default()
+        );
+        if let Some(limitation) = limitation {
+            debug!("Relay {} has limitations {:?}", url, limitation);
+        }
+
+        if limitation.and_then(|l| l.payment_required) == Some(true) {
+            warn!(
+                "Relay {} requires payment. Events may not be served unless you've paid.",
+                url
+            );
+        }
+        if limitation.and_then(|l| l.auth_required) == Some(true) && !auth {
+            warn!(
+                "Relay {} requires authentication. Set nsec and relay_auth for it to serve events.",
+                url
+            );
+        }
+
+        Self {
+            supported_nips: document.supported_nips.clone(),
+            max_filters: limitation
+                .and_then(|l| l.max_filters)
+                .and_then(|max| usize::try_from(max).ok()),
+        }
+    }
+
+    /// Relays that don't list their NIPs are given the benefit of the doubt.
+    fn supports(&self, nip: u16) -> bool {
+        self.supported_nips
+            .as_ref()
+            .map_or(true, |nips| nips.contains(&nip))
+    }
+}
+
+fn is_gift_wrap_filter(filter: &Filter) -> bool {
+    filter
+        .kinds
+        .as_ref()
+        .is_some_and(|kinds| kinds.contains(&Kind::GiftWrap))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters() -> Vec<Filter> {
+        vec![
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().kind(Kind::Reaction),
+            Filter::new().kind(Kind::GiftWrap),
+        ]
+    }
+
+    #[test]
+    fn relays_without_a_document_get_every_filter() {
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        assert_eq!(
+            RelayInfo::default().filters_for(&url, &filters()),
+            vec![filters()]
+        );
+    }
+
+    #[test]
+    fn filters_are_fit_to_the_relay() {
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        let info = RelayInfo(HashMap::from([(
+            url.clone(),
+            Limits {
+                supported_nips: Some(vec![1, 11]),
+                max_filters: Some(1),
+            },
+        )]));
+
+        assert_eq!(
+            info.filters_for(&url, &filters()),
+            vec![
+                vec![Filter::new().kind(Kind::TextNote)],
+                vec![Filter::new().kind(Kind::Reaction)],
+            ]
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use config::{Case, Environment, File, Map, Value, ValueKind};
+use nostr_sdk::PublicKey;
+use serde::{Deserialize, Deserializer};
+
+use crate::messages::{Messages, Templates};
+use crate::nostr::{
+    parse_pubkey, resolve_pubkey, validate_relay_url, EnabledNotifications, MEMORY_DB,
+};
+use crate::ntfy::{DailySummary, Priorities, QuietHours, SenderFilter, Tags, Titles, ZapTiers};
+use crate::sink::SinkKind;
+
+/// Config options redacted by `describe_config`. Their fields in [`Config`]
+/// are [`Secret`]s, so they're redacted from its debug output too.
+const SECRET_OPTIONS: [&str; 5] = [
+    "nsec",
+    "ntfy_token",
+    "ntfy_password",
+    "telegram_bot_token",
+    "discord_webhook_url",
+];
+
+/// A config value that's kept out of the logs. It's shown as `<redacted>`
+/// when debug formatted, and has no `Display` so it can't be printed by
+/// accident.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\"<redacted>\"")
+    }
+}
+
+/// Bullhorn's config, merged from the config file, `BULLHORN_` environment
+/// variables, and defaults.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Config {
+    pub ndb_path: String,
+    /// How long events are kept in the database before being pruned.
+    pub db_retention_secs: Option<u64>,
+    /// Where to also save the subscription QR code as an SVG image.
+    pub qr_output_path: Option<PathBuf>,
+    /// The pubkeys to watch. Accepts a single identifier or a list of them.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub npub: Vec<String>,
+    #[serde(default)]
+    pub event_npubs: Vec<String>,
+    /// `npub` resolved to public keys.
+    #[serde(skip)]
+    pub pubkeys: Vec<PublicKey>,
+    /// `event_npubs` resolved to public keys.
+    #[serde(skip)]
+    pub event_pubkeys: Vec<PublicKey>,
+    /// The types of notifications to send. Defaults to all of them.
+    #[serde(default)]
+    pub enabled_notifications: EnabledNotifications,
+    /// Senders to never notify about.
+    #[serde(default)]
+    pub muted_pubkeys: Vec<String>,
+    /// When set, only these senders are notified about.
+    pub allowed_pubkeys: Option<Vec<String>>,
+    /// `muted_pubkeys` and `allowed_pubkeys` resolved to public keys.
+    #[serde(skip)]
+    pub senders: SenderFilter,
+    pub relays: Option<Vec<String>>,
+    /// The most relays to connect to on startup, taken in order from `relays`.
+    pub max_relays: Option<usize>,
+    /// A SOCKS5 proxy, like Tor, for relay and ntfy connections.
+    pub proxy: Option<String>,
+    /// Where notifications are delivered. Defaults to ntfy.
+    #[serde(default)]
+    pub sink: SinkKind,
+    pub discord_webhook_url: Option<Secret>,
+    pub telegram_bot_token: Option<Secret>,
+    pub telegram_chat_id: Option<String>,
+    pub ntfy_server: Option<String>,
+    pub ntfy_token: Option<Secret>,
+    pub ntfy_user: Option<String>,
+    pub ntfy_password: Option<Secret>,
+    pub ntfy_max_retries: Option<u32>,
+    pub ntfy_timeout_secs: Option<u64>,
+    /// How many notifications to send per second, and how many can go out at
+    /// once before that kicks in. The rest are queued and merged.
+    pub notification_rate_per_sec: Option<f64>,
+    pub notification_burst: Option<u32>,
+    pub web_viewer: Option<String>,
+    #[serde(default)]
+    pub markdown: bool,
+    /// Append the id of the event behind a notification to its message.
+    #[serde(default)]
+    pub debug_include_event_id: bool,
+    pub nsec: Option<Secret>,
+    /// Use `nsec` to authenticate to relays that require NIP-42 AUTH.
+    #[serde(default)]
+    pub relay_auth: bool,
+    pub min_zap_sats: Option<u64>,
+    /// Count zaps whose zap request doesn't verify, like unsigned ones.
+    #[serde(default)]
+    pub count_unverified_zaps: bool,
+    /// Show the approximate value of zaps in this currency, like "usd".
+    pub fiat_currency: Option<String>,
+    pub price_api_url: Option<String>,
+    pub zap_aggregation_secs: Option<u64>,
+    pub dm_aggregation_secs: Option<u64>,
+    pub comment_aggregation_secs: Option<u64>,
+    /// How long to hold comments so ones deleted right away aren't notified about.
+    pub comment_deletion_grace_secs: Option<u64>,
+    /// How many events can wait on the notifier before reactions and reposts
+    /// are dropped.
+    pub event_channel_capacity: Option<usize>,
+    /// Notify about live events that are planned, not only ones that are live.
+    #[serde(default)]
+    pub notify_planned_events: bool,
+    /// Notify about live events ending.
+    #[serde(default)]
+    pub notify_ended_events: bool,
+    /// Notify when a recording of a live event is published.
+    #[serde(default)]
+    pub notify_event_recordings: bool,
+    /// Also notify when someone stops following a watched pubkey.
+    #[serde(default)]
+    pub notify_unfollows: bool,
+    /// Notify again when a watched author edits an article.
+    #[serde(default)]
+    pub notify_article_edits: bool,
+    pub event_reminder_offsets_mins: Option<Vec<u64>>,
+    pub max_pending_reminders: Option<usize>,
+    pub reconnect_max_retries: Option<u32>,
+    pub reconnect_max_backoff_secs: Option<u64>,
+    /// How far back to look for our own notes and for live events on startup.
+    pub author_note_lookback_hours: Option<u64>,
+    pub live_event_lookback_hours: Option<u64>,
+    /// How far to widen `since` filters to make up for a skewed clock.
+    pub clock_skew_tolerance_secs: Option<u64>,
+    /// How long every relay can be disconnected before warning about it.
+    pub relays_down_grace_secs: Option<u64>,
+    #[serde(default)]
+    pub daily_summary: DailySummary,
+    #[serde(default)]
+    pub priorities: Priorities,
+    /// The language to send notifications in. Defaults to English.
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub titles: Titles,
+    /// Overrides for the text of notifications.
+    #[serde(default)]
+    pub templates: Templates,
+    #[serde(default)]
+    pub tags: Tags,
+    #[serde(default)]
+    pub zap_tiers: ZapTiers,
+    pub quiet_hours: Option<QuietHours>,
+    /// Serve `/healthz` and `/metrics` on this port when set.
+    pub metrics_port: Option<u16>,
+}
+
+/// Where bullhorn keeps its state. `BULLHORN_DATA_DIR` overrides the platform
+/// default, which is handy for putting everything under one mounted volume.
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("BULLHORN_DATA_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let dir = dirs::data_dir()
+        .context("Unable to determine the data directory. Set BULLHORN_DATA_DIR.")?;
+    Ok(dir.join("bullhorn"))
+}
+
+/// Where the config and topic live. `BULLHORN_CONFIG_DIR` overrides the
+/// platform default.
+pub fn config_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("BULLHORN_CONFIG_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let dir = dirs::config_dir()
+        .context("Unable to determine the config directory. Set BULLHORN_CONFIG_DIR.")?;
+    Ok(dir.join("bullhorn"))
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Merges the config file, environment variables, and defaults.
+fn load_config() -> Result<config::Config> {
+    let db_filepath = data_dir()?.join("nostr.db");
+    let Some(db_filepath) = db_filepath.to_str() else {
+        bail!(
+            "Data directory {} is not valid UTF-8",
+            db_filepath.display()
+        );
+    };
+
+    let config_file = config_dir()?.join("config.toml");
+
+    Ok(config::Config::builder()
+        .add_source(
+            Environment::default()
+                .prefix("bullhorn")
+                .prefix_separator("_")
+                .convert_case(Case::UpperSnake)
+                .separator("__"),
+        )
+        .add_source(
+            File::from(config_file)
+                .required(false)
+                .format(config::FileFormat::Toml),
+        )
+        .set_default("ndb_path", db_filepath)?
+        .build()?)
+}
+
+/// Loads and validates the config, resolving its pubkeys.
+pub async fn get_config() -> Result<Config> {
+    let mut cfg: Config = load_config()?.try_deserialize()?;
+    cfg.validate()?;
+
+    for identifier in &cfg.npub {
+        cfg.pubkeys.push(resolve_pubkey(identifier).await?);
+    }
+    for identifier in &cfg.event_npubs {
+        cfg.event_pubkeys.push(resolve_pubkey(identifier).await?);
+    }
+    for identifier in &cfg.muted_pubkeys {
+        cfg.senders.muted.insert(resolve_pubkey(identifier).await?);
+    }
+    if let Some(allowed) = &cfg.allowed_pubkeys {
+        let mut pubkeys = HashSet::new();
+        for identifier in allowed {
+            pubkeys.insert(resolve_pubkey(identifier).await?);
+        }
+        cfg.senders.allowed = Some(pubkeys);
+    }
+
+    Ok(cfg)
+}
+
+/// Describes every option that's set along with where it was set, so it's
+/// clear how the config file and environment variables were merged. Secrets
+/// are redacted.
+pub fn describe_config() -> Result<Vec<String>> {
+    let options = load_config()?.collect()?;
+    Ok(config_lines("", &options))
+}
+
+fn config_lines(prefix: &str, options: &Map<String, Value>) -> Vec<String> {
+    let mut keys: Vec<_> = options.keys().collect();
+    keys.sort();
+
+    let mut lines = Vec::new();
+    for key in keys {
+        let value = &options[key];
+        let key = format!("{}{}", prefix, key);
+        if let ValueKind::Table(table) = &value.kind {
+            lines.extend(config_lines(&format!("{}.", key), table));
+            continue;
+        }
+
+        let formatted = if SECRET_OPTIONS.contains(&key.as_str()) {
+            "\"<redacted>\"".to_string()
+        } else {
+            format_config_value(value)
+        };
+        // only defaults have no origin
+        let origin = value.origin().unwrap_or("default");
+        lines.push(format!("{} = {}  # from {}", key, formatted, origin));
+    }
+
+    lines
+}
+
+fn format_config_value(value: &Value) -> String {
+    match &value.kind {
+        ValueKind::String(value) => format!("{:?}", value),
+        ValueKind::Array(values) => {
+            let values: Vec<_> = values.iter().map(format_config_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+impl Config {
+    /// Checks the config for mistakes up front, reporting all of them at once
+    /// rather than only the first.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.npub.is_empty() {
+            problems.push("npub must be set to the npub to be notified about".to_string());
+        }
+        // NIP-05 addresses are checked when they're resolved
+        for identifier in self.npub.iter().chain(&self.event_npubs) {
+            if !identifier.contains('@') {
+                if let Err(err) = parse_pubkey(identifier) {
+                    problems.push(err.to_string());
+                }
+            }
+        }
+        let mut event_npubs = HashSet::new();
+        for identifier in &self.event_npubs {
+            if !event_npubs.insert(identifier.trim()) {
+                problems.push(format!("event_npubs lists '{}' more than once", identifier));
+            }
+        }
+
+        match Path::new(&self.ndb_path).parent() {
+            _ if self.ndb_path == MEMORY_DB => {}
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                if let Err(err) = std::fs::create_dir_all(parent) {
+                    problems.push(format!(
+                        "unable to create the directory for ndb_path {}: {}",
+                        parent.display(),
+                        err
+                    ));
+                }
+            }
+            _ => {}
+        }
+        for relay in self.relays.iter().flatten() {
+            if let Err(err) = validate_relay_url(relay) {
+                problems.push(err.to_string());
+            }
+        }
+
+        problems.extend(self.templates.validate());
+        if self.max_relays == Some(0) {
+            problems.push("max_relays must be at least 1".to_string());
+        }
+        if let Some(rate) = self.notification_rate_per_sec {
+            if !(rate > 0.0 && rate.is_finite()) {
+                problems.push("notification_rate_per_sec must be greater than 0".to_string());
+            }
+        }
+        if self.notification_burst == Some(0) {
+            problems.push("notification_burst must be at least 1".to_string());
+        }
+        if self.event_channel_capacity == Some(0) {
+            problems.push("event_channel_capacity must be at least 1".to_string());
+        }
+        if let Some(currency) = &self.fiat_currency {
+            if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+                problems.push(format!(
+                    "fiat_currency {} is not a three letter currency code",
+                    currency
+                ));
+            }
+        }
+        if let Some(locale) = &self.locale {
+            if Messages::for_locale(locale).is_none() {
+                problems.push(format!("locale {} has no translations", locale));
+            }
+        }
+        if self.relay_auth && self.nsec.is_none() {
+            problems.push("relay_auth requires nsec to be set".to_string());
+        }
+        match self.sink {
+            SinkKind::Discord if self.discord_webhook_url.is_none() => {
+                problems.push("the discord sink requires discord_webhook_url to be set".to_string())
+            }
+            SinkKind::Telegram
+                if self.telegram_bot_token.is_none() || self.telegram_chat_id.is_none() =>
+            {
+                problems.push(
+                    "the telegram sink requires telegram_bot_token and telegram_chat_id to be set"
+                        .to_string(),
+                )
+            }
+            _ => {}
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid config:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_from_debug_output() {
+        let nsec = Some(Secret::new("nsec1secret"));
+        assert_eq!(format!("{:?}", nsec), "Some(\"<redacted>\")");
+        assert_eq!(nsec.as_deref(), Some("nsec1secret"));
+
+        let parsed: Secret = serde_json::from_str("\"tk_secret\"").unwrap();
+        assert_eq!(parsed.expose(), "tk_secret");
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::Debug;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use nostr_sdk::Timestamp;
+use serde::Deserialize;
+
+use crate::ntfy::Priority;
+
+/// A formatted notification, ready to be handed to a sink.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// The type of notification, used for the metrics.
+    pub kind: &'static str,
+    pub title: String,
+    pub message: String,
+    pub priority: Priority,
+    /// Comma separated ntfy tags, which ntfy shows as emojis.
+    pub tags: String,
+    /// Whether the message is formatted with markdown.
+    pub markdown: bool,
+    /// The url to open when the notification is tapped.
+    pub click: Option<String>,
+    pub actions: Vec<Action>,
+    pub icon: Option<String>,
+    /// When to deliver the notification, if not right away. Only set for
+    /// sinks that can schedule delivery.
+    pub at: Option<Timestamp>,
+}
+
+/// A button that opens a url.
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub label: String,
+    pub url: String,
+}
+
+/// Somewhere notifications can be delivered to.
+#[async_trait]
+pub trait NotificationSink: Debug + Send + Sync {
+    async fn deliver(&self, notification: &Notification) -> Result<()>;
+
+    /// Whether the service holds notifications with `at` set until they're
+    /// due. Sinks that can't have them held in-process instead.
+    fn schedules(&self) -> bool {
+        false
+    }
+
+    /// Hands over a notification with `at` set, returning the id the service
+    /// knows it by so it can still be cancelled before it's due.
+    async fn schedule(&self, _notification: &Notification) -> Result<String> {
+        bail!("scheduled delivery isn't supported")
+    }
+
+    /// Cancels a notification handed over with `schedule` that isn't due yet.
+    async fn cancel(&self, _id: &str) -> Result<()> {
+        bail!("scheduled delivery isn't supported")
+    }
+}
+
+/// The service notifications are delivered through.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    #[default]
+    Ntfy,
+    Discord,
+    Telegram,
+}
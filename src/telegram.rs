@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+use crate::ntfy::{Priority, DEFAULT_WEB_VIEWER};
+use crate::sink::{Notification, NotificationSink};
+
+const API_URL: &str = "https://api.telegram.org";
+
+#[derive(Debug, Clone)]
+pub struct TelegramClient {
+    api: reqwest::Client,
+    endpoint: String,
+    chat_id: String,
+    web_viewer: String,
+}
+
+impl TelegramClient {
+    pub fn new(api: reqwest::Client, bot_token: &str, chat_id: impl ToString) -> Self {
+        Self {
+            api,
+            endpoint: format!("{}/bot{}/sendMessage", API_URL, bot_token),
+            chat_id: chat_id.to_string(),
+            web_viewer: DEFAULT_WEB_VIEWER.to_string(),
+        }
+    }
+
+    pub fn with_web_viewer(mut self, web_viewer: &str) -> Self {
+        self.web_viewer = web_viewer.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Telegram only accepts http links on buttons, so `nostr:` uris are
+    /// opened in the web viewer instead.
+    fn button_url(&self, url: &str) -> String {
+        match url.strip_prefix("nostr:") {
+            Some(entity) => format!("{}/{}", self.web_viewer, entity),
+            None => url.to_string(),
+        }
+    }
+
+    fn buttons(&self, notification: &Notification) -> Vec<serde_json::Value> {
+        let links = notification.click.iter().map(|url| ("Open", url)).chain(
+            notification
+                .actions
+                .iter()
+                .map(|action| (action.label.as_str(), &action.url)),
+        );
+
+        let mut urls = Vec::new();
+        let mut buttons = Vec::new();
+        for (label, url) in links {
+            let url = self.button_url(url);
+            if !urls.contains(&url) {
+                buttons.push(json!({ "text": label, "url": url }));
+                urls.push(url);
+            }
+        }
+        buttons
+    }
+
+    async fn post(&self, notification: &Notification) -> Result<()> {
+        let title = match notification.priority {
+            Priority::Max => format!("🚨 {}", notification.title),
+            _ => notification.title.clone(),
+        };
+        let text = format!(
+            "<b>{}</b>\n{}",
+            escape_html(&title),
+            escape_html(&notification.message)
+        );
+        // low priority notifications arrive without a sound
+        let silent = matches!(notification.priority, Priority::Min | Priority::Low);
+
+        let mut body = json!({
+            "chat_id": self.chat_id,
+            "text": text,
+            "parse_mode": "HTML",
+            "disable_notification": silent,
+        });
+        let buttons = self.buttons(notification);
+        if !buttons.is_empty() {
+            body["reply_markup"] = json!({ "inline_keyboard": [buttons] });
+        }
+
+        let response = self.api.post(&self.endpoint).json(&body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Telegram responded with {}: {}", status, body.trim());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NotificationSink for TelegramClient {
+    async fn deliver(&self, notification: &Notification) -> Result<()> {
+        self.post(notification).await
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
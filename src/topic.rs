@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tokio::fs::{create_dir_all, read_to_string, write};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::settings::config_dir;
+
+async fn topic_path() -> Result<PathBuf> {
+    let config_dir = config_dir()?;
+    create_dir_all(&config_dir).await?;
+
+    Ok(config_dir.join("topic"))
+}
+
+/// The ntfy topic notifications are sent to, generating one the first time.
+pub async fn get_subscription_topic() -> Result<Uuid> {
+    let filepath = topic_path().await?;
+    if let Ok(contents) = read_to_string(&filepath).await {
+        Ok(Uuid::parse_str(&contents)?)
+    } else {
+        rotate_subscription_topic().await
+    }
+}
+
+/// Generates a new subscription topic, replacing any existing one.
+pub async fn rotate_subscription_topic() -> Result<Uuid> {
+    let id = Uuid::new_v4();
+    write(topic_path().await?, id.as_hyphenated().to_string()).await?;
+    info!("Generated a new subscription topic");
+
+    Ok(id)
+}